@@ -0,0 +1,73 @@
+//! Compares reading env files one after another with the concurrent reader
+//! used by `envy export`.
+//!
+//! Run with `cargo bench`.
+
+#[cfg(feature = "encryption")]
+#[allow(dead_code)]
+#[path = "../src/crypto.rs"]
+mod crypto;
+// Its unit tests are compiled without the test harness here
+#[allow(dead_code, unused_imports)]
+#[path = "../src/env_file.rs"]
+mod env_file;
+
+use std::{
+    env, fs,
+    path::PathBuf,
+    process,
+    time::{Duration, Instant},
+};
+
+const FILES: usize = 50;
+const VARS_PER_FILE: usize = 100;
+const ITERATIONS: u32 = 200;
+
+fn bench(name: &str, mut f: impl FnMut()) -> Duration {
+    let start = Instant::now();
+    for _ in 0..ITERATIONS {
+        f();
+    }
+    let elapsed = start.elapsed() / ITERATIONS;
+    println!("{name:<12} {elapsed:?} per iteration");
+    elapsed
+}
+
+fn main() {
+    let dir = env::temp_dir().join(format!("envy-bench-{}", process::id()));
+    fs::create_dir_all(&dir).expect("Cannot create benchmark directory");
+
+    let files: Vec<PathBuf> = (0..FILES)
+        .map(|i| {
+            let file = dir.join(format!("{i}.env"));
+            let content: String = (0..VARS_PER_FILE)
+                .map(|j| format!("# variable {j}\nVAR_{i}_{j}=value_{j}\n"))
+                .collect();
+            fs::write(&file, content).expect("Cannot write env file");
+            file
+        })
+        .collect();
+
+    println!("Reading {FILES} env files with {VARS_PER_FILE} variables each");
+    let sequential = bench("sequential", || {
+        let vars: Vec<String> = files
+            .iter()
+            .flat_map(|file| env_file::get_env_vars_from_file(file).unwrap())
+            .collect();
+        assert_eq!(vars.len(), FILES * VARS_PER_FILE);
+    });
+    let concurrent = bench("concurrent", || {
+        let vars: Vec<String> = env_file::get_env_vars_per_file(&files)
+            .unwrap()
+            .into_iter()
+            .flatten()
+            .collect();
+        assert_eq!(vars.len(), FILES * VARS_PER_FILE);
+    });
+    println!(
+        "speedup      {:.2}x",
+        sequential.as_secs_f64() / concurrent.as_secs_f64()
+    );
+
+    fs::remove_dir_all(&dir).expect("Cannot remove benchmark directory");
+}
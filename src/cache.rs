@@ -0,0 +1,145 @@
+use anyhow::{Context, Result};
+use directories::BaseDirs;
+use std::{
+    collections::hash_map::DefaultHasher,
+    env, fs,
+    hash::{Hash, Hasher},
+    path::{Path, PathBuf},
+    time::UNIX_EPOCH,
+};
+
+use crate::settings::ignore_root;
+use crate::write_private;
+
+/// Stands in for the modification time of an input that doesn't exist
+const MISSING: &str = "-";
+
+/// Cached output of a single `envy export` invocation.
+///
+/// The hook runs `envy export` on every prompt, so we keep the last output per
+/// directory and command line around. An entry is only reused if the
+/// modification times of the config file and all env files that went into it
+/// are unchanged, as well as the variables from the environment it used.
+/// Inputs that didn't exist must still be missing, so that creating an env
+/// file that would have been loaded is noticed as well.
+/// Outputs that depend on encrypted env files are never cached, so that
+/// their values only ever get decrypted in memory.
+pub struct Cache {
+    path: PathBuf,
+}
+
+impl Cache {
+    pub fn new(config: &Path, dir: &Path) -> Result<Self> {
+        let base_dirs = BaseDirs::new().context("Cannot get base directories")?;
+        let mut hasher = DefaultHasher::new();
+        // Newer versions might interpret the same config differently,
+        // so start over whenever envy gets updated
+        env!("CARGO_PKG_VERSION").hash(&mut hasher);
+        env::current_exe()
+            .ok()
+            .and_then(|exe| mtime(&exe))
+            .hash(&mut hasher);
+        config.hash(&mut hasher);
+        dir.hash(&mut hasher);
+        // Adding or removing an `.envignore` changes the matching env files
+        // without touching any of them
+        ignore_root(dir).hash(&mut hasher);
+        // The shell might be detected from `$SHELL` instead of being passed
+        env::var_os("SHELL").hash(&mut hasher);
+        for arg in env::args_os().skip(1) {
+            arg.hash(&mut hasher);
+        }
+        let path = base_dirs
+            .cache_dir()
+            .join("envy")
+            .join(format!("{:016x}", hasher.finish()));
+        Ok(Cache { path })
+    }
+
+    /// Get the cached output if none of its inputs changed since it was written
+    pub fn get(&self, config: &Path) -> Option<String> {
+        let content = fs::read_to_string(&self.path).ok()?;
+        let (header, output) = content.split_once("\n\n")?;
+        let mut lines = header.lines();
+        if lines.next()? != mtime(config)? {
+            return None;
+        }
+        for line in lines {
+            if let Some(var) = line.strip_prefix('$') {
+                let (key, hash) = var.split_once(' ')?;
+                if hash != env_hash(key) {
+                    return None;
+                }
+                continue;
+            }
+            let (stamp, file) = line.split_once(' ')?;
+            // Entries of older versions might contain decrypted values
+            if is_encrypted(Path::new(file)) {
+                return None;
+            }
+            if stamp != mtime(Path::new(file)).as_deref().unwrap_or(MISSING) {
+                return None;
+            }
+        }
+        Some(output.to_string())
+    }
+
+    /// Store the output together with the modification times of its inputs
+    /// (or whether they are missing)
+    /// and the (hashed) values of the given environment variables
+    pub fn set(
+        &self,
+        config: &Path,
+        env_files: &[PathBuf],
+        env_keys: &[String],
+        output: &str,
+    ) -> Result<()> {
+        // Decrypted values must not end up on the disk
+        if env_files.iter().any(|file| is_encrypted(file)) {
+            let _ = fs::remove_file(&self.path);
+            return Ok(());
+        }
+        let mut content = mtime(config).context("Cannot get config modification time")?;
+        content.push('\n');
+        for file in env_files {
+            let stamp = mtime(file).unwrap_or_else(|| MISSING.to_string());
+            content.push_str(&format!("{stamp} {}\n", file.display()));
+        }
+        for key in env_keys {
+            content.push_str(&format!("${key} {}\n", env_hash(key)));
+        }
+        content.push('\n');
+        content.push_str(output);
+
+        if let Some(parent) = self.path.parent() {
+            fs::create_dir_all(parent).context("Cannot create cache directory")?;
+        }
+        write_private(&self.path, content.as_bytes()).context("Cannot write cache")
+    }
+}
+
+/// Check if the output of an input would contain decrypted values
+fn is_encrypted(file: &Path) -> bool {
+    #[cfg(feature = "encryption")]
+    return crate::crypto::is_encrypted(file);
+    #[cfg(not(feature = "encryption"))]
+    {
+        let _ = file;
+        false
+    }
+}
+
+/// Hash of the value of the given environment variable, so that the cache
+/// doesn't contain it in plain text
+fn env_hash(key: &str) -> String {
+    let mut hasher = DefaultHasher::new();
+    env::var_os(key).hash(&mut hasher);
+    format!("{:016x}", hasher.finish())
+}
+
+/// Modification time of the given file in nanoseconds since the epoch
+fn mtime(path: &Path) -> Option<String> {
+    let modified = fs::metadata(path).ok()?.modified().ok()?;
+    let nanos = modified.duration_since(UNIX_EPOCH).ok()?.as_nanos();
+    Some(nanos.to_string())
+}
@@ -0,0 +1,42 @@
+use age::armor::ArmoredReader;
+use anyhow::{anyhow, Context, Result};
+use std::{env, fs, io::Read, path::Path};
+
+/// Check if the env file is encrypted with age, based on its extension
+pub fn is_encrypted(env: &Path) -> bool {
+    env.extension().is_some_and(|ext| ext == "age")
+}
+
+/// Decrypt an age-encrypted env file in memory
+///
+/// The identity is read from `ENVY_KEY` (e.g. `AGE-SECRET-KEY-1...`) or from
+/// the identity file that `ENVY_KEY_FILE` points to.
+pub fn decrypt_file(env: &Path) -> Result<String> {
+    let identities = match (env::var("ENVY_KEY"), env::var("ENVY_KEY_FILE")) {
+        (Ok(key), _) => age::IdentityFile::from_buffer(key.as_bytes())
+            .context("Cannot parse identity from ENVY_KEY")?,
+        (_, Ok(key_file)) => age::IdentityFile::from_file(key_file)
+            .context("Cannot read identity file from ENVY_KEY_FILE")?,
+        _ => {
+            return Err(anyhow!(
+                "Set ENVY_KEY or ENVY_KEY_FILE to decrypt {}",
+                env.display()
+            ))
+        }
+    }
+    .into_identities()
+    .context("Cannot parse identities")?;
+
+    // Only report the kind of error, never any of the file content
+    let file = fs::File::open(env).context("Cannot read env file")?;
+    let decryptor = age::Decryptor::new(ArmoredReader::new(file))
+        .map_err(|e| anyhow!("Cannot decrypt {}: {e}", env.display()))?;
+    let mut reader = decryptor
+        .decrypt(identities.iter().map(|identity| identity.as_ref()))
+        .map_err(|e| anyhow!("Cannot decrypt {}: {e}", env.display()))?;
+    let mut content = String::new();
+    reader
+        .read_to_string(&mut content)
+        .map_err(|_| anyhow!("Cannot decrypt {}: invalid content", env.display()))?;
+    Ok(content)
+}
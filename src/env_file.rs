@@ -0,0 +1,503 @@
+use anyhow::{anyhow, bail, Context, Result};
+use std::{
+    fs,
+    io::Read,
+    path::{Path, PathBuf},
+    process::{Command, Stdio},
+    str::FromStr,
+    thread,
+};
+
+/// Syntax of the variables in an env file
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub enum EnvFormat {
+    /// `KEY=value`
+    Dotenv,
+    /// `KEY: value`, as emitted by some tools
+    Yaml,
+}
+
+impl EnvFormat {
+    /// Get the format of a file from its extension, e.g. `.yml`
+    ///
+    /// Encrypted files are detected by the extension before `.age`.
+    pub fn from_path(path: &Path) -> Self {
+        let name = path.file_name().unwrap_or_default().to_string_lossy();
+        let name = name.strip_suffix(".age").unwrap_or(&name);
+        if name.ends_with(".yaml") || name.ends_with(".yml") {
+            EnvFormat::Yaml
+        } else {
+            EnvFormat::Dotenv
+        }
+    }
+
+    /// Turn a line of this format into a `KEY=value` line
+    ///
+    /// Only the first colon separates the key, so values can be URLs.
+    fn normalize(self, line: String) -> String {
+        match self {
+            EnvFormat::Dotenv => line,
+            EnvFormat::Yaml => match line.split_once(':') {
+                Some((key, value)) => format!("{}={}", key.trim_end(), value.trim()),
+                None => line,
+            },
+        }
+    }
+}
+
+impl FromStr for EnvFormat {
+    type Err = anyhow::Error;
+
+    fn from_str(format: &str) -> Result<Self> {
+        match format {
+            "dotenv" | "env" => Ok(EnvFormat::Dotenv),
+            "yaml" | "yml" => Ok(EnvFormat::Yaml),
+            _ => Err(anyhow!("Unknown format {format} (supported: dotenv, yaml)")),
+        }
+    }
+}
+
+/// Get all environment variables from the given file
+///
+/// A line like `# envy: include common.env` splices in the variables of
+/// another env file at that position. Relative paths are resolved against
+/// the directory of the including file.
+/// Files ending in `.yaml` or `.yml` contain `KEY: value` lines instead.
+pub fn get_env_vars_from_file(env: &Path) -> Result<Vec<String>> {
+    get_env_vars_from_file_as(env, EnvFormat::from_path(env))
+}
+
+/// Get all environment variables from the given file in the given format
+///
+/// Included files still get the format of their extension.
+pub fn get_env_vars_from_file_as(env: &Path, format: EnvFormat) -> Result<Vec<String>> {
+    let mut env_vars = Vec::new();
+    read_env_file(env, format, &mut Vec::new(), &mut env_vars)?;
+    Ok(env_vars)
+}
+
+/// Get all environment variables from the given reader, e.g. stdin
+///
+/// Includes are resolved against the current directory.
+pub fn get_env_vars_from_reader(mut reader: impl Read, format: EnvFormat) -> Result<Vec<String>> {
+    let mut content = String::new();
+    reader
+        .read_to_string(&mut content)
+        .context("Cannot read env vars")?;
+    let mut env_vars = Vec::new();
+    let env = Path::new("-");
+    parse_env_vars(&content, env, format, &mut Vec::new(), &mut env_vars)?;
+    Ok(env_vars)
+}
+
+/// Get all environment variables from the output of a shell command
+///
+/// This keeps secrets from a password manager off the disk. The command
+/// inherits stdin and stderr, so it can still ask for a password.
+pub fn get_env_vars_from_command(command: &str, format: EnvFormat) -> Result<Vec<String>> {
+    let mut shell = if cfg!(windows) {
+        let mut shell = Command::new("cmd");
+        shell.arg("/C");
+        shell
+    } else {
+        let mut shell = Command::new("sh");
+        shell.arg("-c");
+        shell
+    };
+    let output = shell
+        .arg(command)
+        .stdin(Stdio::inherit())
+        .stderr(Stdio::inherit())
+        .output()
+        .with_context(|| format!("Cannot run {command}"))?;
+    if !output.status.success() {
+        bail!("Command `{command}` failed with {}", output.status);
+    }
+    get_env_vars_from_reader(output.stdout.as_slice(), format)
+}
+
+fn read_env_file(
+    env: &Path,
+    format: EnvFormat,
+    parents: &mut Vec<PathBuf>,
+    env_vars: &mut Vec<String>,
+) -> Result<()> {
+    let content = read_env_file_content(env)?;
+    let path = env.canonicalize().context("Cannot read env file")?;
+    if parents.contains(&path) {
+        bail!("Env file includes itself recursively: {}", path.display());
+    }
+    parents.push(path);
+    parse_env_vars(&content, env, format, parents, env_vars)?;
+    parents.pop();
+    Ok(())
+}
+
+fn parse_env_vars(
+    content: &str,
+    env: &Path,
+    format: EnvFormat,
+    parents: &mut Vec<PathBuf>,
+    env_vars: &mut Vec<String>,
+) -> Result<()> {
+    for line in parse_env_lines(content) {
+        match include_path(env, &line) {
+            Some(include) => {
+                let include_format = EnvFormat::from_path(&include);
+                read_env_file(&include, include_format, parents, env_vars)
+                    .with_context(|| format!("Cannot include {}", include.display()))?
+            }
+            None => env_vars.push(format.normalize(line)),
+        }
+    }
+    Ok(())
+}
+
+/// Get the lines of the content of an env file that define variables
+///
+/// Blank lines and comments are dropped and continued lines are joined.
+/// Include directives are kept as they are, since resolving them requires
+/// reading other files.
+pub fn parse_env_lines(content: &str) -> Vec<String> {
+    // Editors on Windows like to add a byte order mark and CRLF line endings
+    let content = content.strip_prefix('\u{feff}').unwrap_or(content);
+    join_continued_lines(content)
+        .into_iter()
+        .filter(|line| is_env_var(line) || is_include(line))
+        .collect()
+}
+
+/// Join variables that span multiple lines with a trailing backslash,
+/// like `KEY=first\` followed by `second`, into single lines
+fn join_continued_lines(content: &str) -> Vec<String> {
+    let mut lines = Vec::new();
+    let mut continued: Option<String> = None;
+    for line in content.lines() {
+        // `lines` only strips a carriage return that is followed by a newline
+        let line = line.trim_end_matches('\r');
+        let mut joined = continued.take().unwrap_or_default();
+        // Comments can't be continued, but a continued value can look like one
+        let is_var = !joined.is_empty() || is_env_var(line);
+        // An escaped backslash (`\\`) at the end doesn't continue the line
+        let backslashes = line.len() - line.trim_end_matches('\\').len();
+        if is_var && backslashes % 2 == 1 {
+            joined.push_str(&line[..line.len() - 1]);
+            continued = Some(joined);
+        } else {
+            joined.push_str(line);
+            lines.push(joined);
+        }
+    }
+    // A backslash on the last line has nothing to continue with
+    lines.extend(continued);
+    lines
+}
+
+fn read_env_file_content(env: &Path) -> Result<String> {
+    #[cfg(feature = "encryption")]
+    if crate::crypto::is_encrypted(env) {
+        return crate::crypto::decrypt_file(env);
+    }
+    fs::read_to_string(env).context("Cannot read env file")
+}
+
+/// Get the path of the file included by the line, if it is an include directive
+fn include_path(env: &Path, line: &str) -> Option<PathBuf> {
+    let include = include_target(line)?;
+    let dir = env.parent().unwrap_or_else(|| Path::new(""));
+    Some(dir.join(include))
+}
+
+/// Get the file named by an include directive like `# envy: include common.env`
+fn include_target(line: &str) -> Option<&str> {
+    directive(line, "include")
+}
+
+/// Get the argument of a directive like `# envy: <name> <argument>`
+fn directive<'a>(line: &'a str, name: &str) -> Option<&'a str> {
+    let argument = line
+        .trim()
+        .strip_prefix('#')?
+        .trim_start()
+        .strip_prefix("envy:")?
+        .trim_start()
+        .strip_prefix(name)?
+        .strip_prefix(' ')?
+        .trim();
+    Some(argument)
+}
+
+fn is_include(line: &str) -> bool {
+    include_target(line).is_some()
+}
+
+/// Get all files that the given env files include, directly or indirectly
+///
+/// Files that cannot be read are skipped, since reading the variables
+/// reports them anyway.
+pub fn get_included_env_files(envs: &[PathBuf]) -> Vec<PathBuf> {
+    let mut included = Vec::new();
+    let mut pending = envs.to_vec();
+    while let Some(env) = pending.pop() {
+        let Ok(content) = fs::read_to_string(&env) else {
+            continue;
+        };
+        for line in content.lines() {
+            let Some(include) = include_path(&env, line).and_then(|p| p.canonicalize().ok()) else {
+                continue;
+            };
+            if !included.contains(&include) {
+                included.push(include.clone());
+                pending.push(include);
+            }
+        }
+    }
+    included
+}
+
+/// Get the files that the given env files declare with a directive like
+/// `# envy: watch_file config.json`, similar to direnv's `watch_file`
+///
+/// A change to any of them invalidates the cached export and triggers
+/// `envy watch`, even though they don't contain variables.
+/// Relative paths are resolved against the directory of the env file.
+pub fn get_watched_files(envs: &[PathBuf]) -> Vec<PathBuf> {
+    let mut watched = Vec::new();
+    for env in envs {
+        let Ok(content) = fs::read_to_string(env) else {
+            continue;
+        };
+        let dir = env.parent().unwrap_or_else(|| Path::new(""));
+        for line in content.lines() {
+            if let Some(file) = directive(line, "watch_file") {
+                let file = dir.join(file);
+                if !watched.contains(&file) {
+                    watched.push(file);
+                }
+            }
+        }
+    }
+    watched
+}
+
+/// Check if the line defines a variable rather than being blank or a comment
+pub fn is_env_var(line: &str) -> bool {
+    let line = line.trim_start();
+    !line.is_empty() && !line.starts_with('#')
+}
+
+/// Check if the key is a valid variable name according to POSIX,
+/// i.e. it only consists of letters, digits and underscores
+/// and doesn't start with a digit
+pub fn is_valid_env_key(key: &str) -> bool {
+    let mut chars = key.chars();
+    matches!(chars.next(), Some(c) if c.is_ascii_alphabetic() || c == '_')
+        && chars.all(|c| c.is_ascii_alphanumeric() || c == '_')
+}
+
+/// Split an env var line like `export KEY=value` into its key and value
+///
+/// Unquoted values get trimmed, while quoted values keep their whitespace
+/// and lose their quotes.
+pub fn split_env_var(var: &str) -> Option<(&str, &str)> {
+    let (key, value) = split_env_var_raw(var)?;
+    Some((key, unquote(value)))
+}
+
+/// Split an env var line like `split_env_var`, but keep the quotes of the value
+pub fn split_env_var_raw(var: &str) -> Option<(&str, &str)> {
+    let var = strip_export_prefix(var);
+    let (key, value) = var.split_once('=')?;
+    Some((key.trim(), value.trim()))
+}
+
+/// Strip a leading `export` from a line like `export\tKEY=value`
+///
+/// The keyword is matched regardless of case and can be followed by
+/// any whitespace, but a variable named `export` is left alone.
+pub fn strip_export_prefix(var: &str) -> &str {
+    let var = var.trim_start();
+    match var.get(..6) {
+        Some(prefix) if prefix.eq_ignore_ascii_case("export") => {
+            let rest = &var[6..];
+            let stripped = rest.trim_start();
+            if stripped.len() < rest.len() {
+                stripped
+            } else {
+                var
+            }
+        }
+        _ => var,
+    }
+}
+
+/// Get the name of the variable that a line like `unset KEY` removes
+pub fn split_env_unset(var: &str) -> Option<&str> {
+    let key = var.trim().strip_prefix("unset ")?.trim();
+    is_valid_env_key(key).then_some(key)
+}
+
+/// Get the name of the variable that a line sets or unsets
+pub fn env_key(var: &str) -> Option<&str> {
+    split_env_var(var)
+        .map(|(key, _)| key)
+        .or_else(|| split_env_unset(var))
+}
+
+/// Split an append like `PATH+=/opt/bin` into its key and value
+pub fn split_env_append(var: &str) -> Option<(&str, &str)> {
+    let (key, value) = split_env_var(var)?;
+    Some((key.strip_suffix('+')?.trim_end(), value))
+}
+
+fn unquote(value: &str) -> &str {
+    for quote in ['"', '\''] {
+        if let Some(inner) = value
+            .strip_prefix(quote)
+            .and_then(|value| value.strip_suffix(quote))
+        {
+            return inner;
+        }
+    }
+    value
+}
+
+/// Get the environment variables of each of the given files
+///
+/// The files are split up between a few threads and read concurrently.
+/// The variables are still returned in the order of the given files,
+/// so that variables from later files can override earlier ones.
+pub fn get_env_vars_per_file(envs: &[PathBuf]) -> Result<Vec<Vec<String>>> {
+    let workers = thread::available_parallelism().map_or(1, |n| n.get());
+    let chunk_size = envs.len().div_ceil(workers).max(1);
+    thread::scope(|scope| {
+        let readers: Vec<_> = envs
+            .chunks(chunk_size)
+            .map(|chunk| {
+                scope.spawn(move || {
+                    chunk
+                        .iter()
+                        .map(|env| get_env_vars_from_file(env))
+                        .collect::<Result<Vec<_>>>()
+                })
+            })
+            .collect();
+
+        let mut env_vars = Vec::new();
+        for reader in readers {
+            env_vars.extend(reader.join().expect("Cannot join env file reader")?);
+        }
+        Ok(env_vars)
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn strip_byte_order_mark_and_crlf() {
+        let content = "\u{feff}FOO=bar\r\nBAZ=qux\r\n";
+        assert_eq!(parse_env_lines(content), ["FOO=bar", "BAZ=qux"]);
+    }
+
+    #[test]
+    fn trim_stray_carriage_returns() {
+        // A carriage return without a newline is left by `lines` otherwise
+        let content = "FOO=bar\r\r\nBAZ=qux\r";
+        assert_eq!(parse_env_lines(content), ["FOO=bar", "BAZ=qux"]);
+    }
+
+    #[test]
+    fn trim_unquoted_values_only() {
+        assert_eq!(split_env_var("A=\"  x  \""), Some(("A", "  x  ")));
+        assert_eq!(split_env_var("B=  x  "), Some(("B", "x")));
+        assert_eq!(split_env_var_raw("A=\"  x  \""), Some(("A", "\"  x  \"")));
+    }
+
+    #[test]
+    fn valid_env_keys() {
+        assert!(is_valid_env_key("FOO_1"));
+        assert!(is_valid_env_key("_foo"));
+        assert!(!is_valid_env_key("1FOO"));
+        assert!(!is_valid_env_key("FOO-BAR"));
+        assert!(!is_valid_env_key(""));
+    }
+
+    /// Write the files into a new temporary directory and get its path
+    fn write_files(name: &str, files: &[(&str, &str)]) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("envy-unit-{name}-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        for (file, content) in files {
+            let file = dir.join(file);
+            fs::create_dir_all(file.parent().unwrap()).unwrap();
+            fs::write(file, content).unwrap();
+        }
+        dir
+    }
+
+    #[test]
+    fn include_env_file() {
+        let dir = write_files(
+            "include",
+            &[
+                ("app.env", "A=1\n# envy: include common.env\nC=3\n"),
+                ("common.env", "B=2\n"),
+            ],
+        );
+        let vars = get_env_vars_from_file(&dir.join("app.env")).unwrap();
+        assert_eq!(vars, ["A=1", "B=2", "C=3"]);
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn include_relative_to_including_file() {
+        let dir = write_files(
+            "include-relative",
+            &[
+                ("sub/app.env", "# envy: include ../common.env\n"),
+                ("common.env", "B=2\n"),
+            ],
+        );
+        let vars = get_env_vars_from_file(&dir.join("sub/app.env")).unwrap();
+        assert_eq!(vars, ["B=2"]);
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn include_itself() {
+        let dir = write_files("include-self", &[("app.env", "# envy: include app.env\n")]);
+        let error = get_env_vars_from_file(&dir.join("app.env")).unwrap_err();
+        assert!(format!("{error:#}").contains("includes itself recursively"));
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn continued_lines() {
+        assert_eq!(parse_env_lines("A=one\\\ntwo\n"), ["A=onetwo"]);
+        assert_eq!(
+            parse_env_lines("A=one \\\ntwo \\\nthree\n"),
+            ["A=one two three"]
+        );
+        // An escaped backslash ends the value
+        assert_eq!(
+            parse_env_lines("A=one\\\\\nB=two\n"),
+            ["A=one\\\\", "B=two"]
+        );
+    }
+
+    #[test]
+    fn drop_comments_and_blank_lines() {
+        let content = "# comment\n\n  # indented comment\nFOO=bar # not a comment\n   \n";
+        assert_eq!(parse_env_lines(content), ["FOO=bar # not a comment"]);
+    }
+
+    #[test]
+    fn keep_include_directives() {
+        let content = "\u{feff}A=1\r\n#  envy: include  ../common.env \r\n# envy: watch_file x\nB=one\\\ntwo\n";
+        assert_eq!(
+            parse_env_lines(content),
+            ["A=1", "#  envy: include  ../common.env ", "B=onetwo"]
+        );
+    }
+}
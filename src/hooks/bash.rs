@@ -1,12 +1,15 @@
+use super::render_hook;
 use anyhow::Result;
-use std::env::current_exe;
 
 // Shamelessly taken from direnv
 // https://github.com/direnv/direnv/blob/e54386bdcccf9c7eea5976f787c4c31ddb5157d5/shell_bash.go
 static BASH_HOOK: &str = r#" 
 _envy_hook() {
     local previous_exit_status=$?;
-    eval "$("{{.SelfPath}}" export bash)";
+    if [[ -z "$ENVY_DISABLE" || "$ENVY_DISABLE" == 0 ]]; then
+      local envy_output;
+      envy_output="$(ENVY_EXPORTING=1 ENVY_HOOK_VERSION={{.Version}} "{{.SelfPath}}" export bash)" && eval "$envy_output";
+    fi
     return $previous_exit_status;
   };
   if ! [[ "$PROMPT_COMMAND" =~ _envy_hook ]]; then
@@ -18,6 +21,6 @@ pub struct Bash;
 
 impl Bash {
     pub fn hook() -> Result<String> {
-        Ok(BASH_HOOK.replace("{{.SelfPath}}", &current_exe()?.to_string_lossy()))
+        render_hook(BASH_HOOK)
     }
 }
@@ -1,9 +1,11 @@
+use super::render_hook;
 use anyhow::Result;
-use std::env::current_exe;
 
 static FISH_HOOK: &str = r#"
 function __direnv_export_eval --on-event fish_prompt;
-	eval ("{{.SelfPath}}" export fish);
+	test -n "$ENVY_DISABLE"; and test "$ENVY_DISABLE" != 0; and return;
+	set -l envy_output (env ENVY_EXPORTING=1 ENVY_HOOK_VERSION={{.Version}} "{{.SelfPath}}" export fish); or return;
+	string join \n -- $envy_output | source;
 end
 "#;
 
@@ -11,6 +13,6 @@ pub struct Fish;
 
 impl Fish {
     pub fn hook() -> Result<String> {
-        Ok(FISH_HOOK.replace("{{.SelfPath}}", &current_exe()?.to_string_lossy()))
+        render_hook(FISH_HOOK)
     }
 }
@@ -1,3 +1,43 @@
 pub mod bash;
 pub mod fish;
+pub mod tcsh;
+pub mod xonsh;
 pub mod zsh;
+
+use anyhow::Result;
+use std::{
+    env::{self, current_exe},
+    path::{Path, PathBuf},
+};
+
+/// Fill in the template variables of a hook
+pub fn render_hook(template: &str) -> Result<String> {
+    Ok(template
+        .replace("{{.SelfPath}}", &self_path()?)
+        .replace("{{.Version}}", env!("CARGO_PKG_VERSION")))
+}
+
+/// Get the path of the envy binary to bake into the hooks
+///
+/// `current_exe` resolves symlinks, which breaks installs where only the
+/// symlink stays stable (e.g. Homebrew). So prefer the path envy was invoked
+/// with, as long as it points to an existing file.
+pub fn self_path() -> Result<String> {
+    let invoked = env::args_os().next().map(PathBuf::from);
+    let path = match invoked {
+        Some(path) if path.is_absolute() && path.is_file() => path,
+        // A bare command name was looked up on the `PATH`
+        Some(path) if path.components().count() == 1 => match find_in_path(&path) {
+            Some(path) => path,
+            None => current_exe()?,
+        },
+        _ => current_exe()?,
+    };
+    Ok(path.to_string_lossy().into_owned())
+}
+
+fn find_in_path(name: &Path) -> Option<PathBuf> {
+    env::split_paths(&env::var_os("PATH")?)
+        .map(|dir| dir.join(name))
+        .find(|path| path.is_absolute() && path.is_file())
+}
@@ -0,0 +1,16 @@
+use super::render_hook;
+use anyhow::Result;
+
+// Shamelessly taken from direnv
+// https://github.com/direnv/direnv/blob/e54386bdcccf9c7eea5976f787c4c31ddb5157d5/shell_tcsh.go
+static TCSH_HOOK: &str = r#"
+alias precmd 'eval `env ENVY_EXPORTING=1 ENVY_HOOK_VERSION={{.Version}} "{{.SelfPath}}" export tcsh`'
+"#;
+
+pub struct Tcsh;
+
+impl Tcsh {
+    pub fn hook() -> Result<String> {
+        render_hook(TCSH_HOOK)
+    }
+}
@@ -0,0 +1,22 @@
+use super::render_hook;
+use anyhow::Result;
+
+// xonsh handlers of `on_pre_prompt` run before each prompt and must accept
+// keyword arguments, even though this event doesn't pass any
+static XONSH_HOOK: &str = r#"
+@events.on_pre_prompt
+def _envy_hook(**kwargs):
+    if ${...}.get("ENVY_DISABLE", "") not in ("", "0"):
+        return
+    envy_output = $(env ENVY_EXPORTING=1 ENVY_HOOK_VERSION={{.Version}} "{{.SelfPath}}" export xonsh)
+    if envy_output:
+        execx(envy_output)
+"#;
+
+pub struct Xonsh;
+
+impl Xonsh {
+    pub fn hook() -> Result<String> {
+        render_hook(XONSH_HOOK)
+    }
+}
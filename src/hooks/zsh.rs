@@ -1,11 +1,13 @@
+use super::render_hook;
 use anyhow::Result;
-use std::env::current_exe;
 
 // Shamelessly taken from direnv
 // https://github.com/direnv/direnv/blob/e54386bdcccf9c7eea5976f787c4c31ddb5157d5/shell_zsh.go
 static ZSH_HOOK: &str = r#" 
 _envy_hook() {
-    eval "$("{{.SelfPath}}" export zsh)";
+    [[ -n "$ENVY_DISABLE" && "$ENVY_DISABLE" != 0 ]] && return;
+    local envy_output;
+    envy_output="$(ENVY_EXPORTING=1 ENVY_HOOK_VERSION={{.Version}} "{{.SelfPath}}" export zsh)" && eval "$envy_output";
 }
 typeset -ag precmd_functions;
 if [[ -z ${precmd_functions[(r)_envy_hook]} ]]; then
@@ -17,6 +19,6 @@ pub struct Zsh;
 
 impl Zsh {
     pub fn hook() -> Result<String> {
-        Ok(ZSH_HOOK.replace("{{.SelfPath}}", &current_exe()?.to_string_lossy()))
+        render_hook(ZSH_HOOK)
     }
 }
@@ -1,149 +1,649 @@
+use ansi_term::{Colour, Style};
 use anyhow::{anyhow, Context, Result};
+use base64::{engine::general_purpose::STANDARD as BASE64, Engine as _};
 
+mod cache;
+#[cfg(feature = "encryption")]
+mod crypto;
+mod env_file;
 mod hooks;
 mod opt;
 mod settings;
+mod shell;
 
+use serde_json::json;
+use std::borrow::Cow;
+use std::collections::{BTreeMap, BTreeSet};
+use std::io::{self, IsTerminal, Write};
 use std::path::{Path, PathBuf};
-use std::process;
-use std::{env::current_dir, fs};
+use std::sync::{mpsc, Arc, Mutex};
+use std::time::Duration;
+use std::{
+    env::{self, current_dir},
+    fs,
+};
+use std::{process, thread};
 use structopt::StructOpt;
 
+use cache::Cache;
 use directories::BaseDirs;
+use env_file::{
+    env_key, get_env_vars_from_command, get_env_vars_from_file, get_env_vars_from_file_as,
+    get_env_vars_from_reader, get_env_vars_per_file, get_included_env_files, get_watched_files,
+    is_env_var, is_valid_env_key, split_env_append, split_env_unset, split_env_var,
+    split_env_var_raw, EnvFormat,
+};
 use hooks::zsh::Zsh;
-use opt::{Command, Envy};
-use settings::Settings;
+use notify::{RecursiveMode, Watcher};
+use opt::{Command, ConfigCommand, Envy, ExportOptions};
+use settings::{wildcard_match, EnvySettings, Settings, CONFIG_TEMPLATE, PROJECT_CONFIG_NAME};
+use shell::{unsupported_shell, Shell, SUPPORTED_SHELLS};
 
+/// Get the path of the config file, e.g. `~/.config/envy/Config.toml` on Linux
 fn config_path() -> Result<PathBuf> {
+    // Check `XDG_CONFIG_HOME` ourselves, so that it always takes precedence.
+    // Relative paths are invalid according to the spec and get ignored.
+    #[cfg(target_os = "linux")]
+    if let Some(config_home) = env::var_os("XDG_CONFIG_HOME").map(PathBuf::from) {
+        if config_home.is_absolute() {
+            return Ok(config_home.join("envy").join("Config.toml"));
+        }
+    }
     let base_dirs = BaseDirs::new().context("Cannot get base directories")?;
     Ok(base_dirs.config_dir().join("envy").join("Config.toml"))
 }
 
 fn main() -> Result<()> {
     let opt = Envy::from_args();
+    let color = opt.use_color();
     match opt.cmd {
-        Command::Hook { shell } => hook(shell),
-        Command::Export { shell } => export(shell),
+        Command::Hook { shell, print_path } => hook(shell, print_path),
+        Command::Export {
+            shell_detect: true, ..
+        } => {
+            println!("{}", Shell::detect().name());
+            Ok(())
+        }
+        Command::Export {
+            shell,
+            options,
+            output,
+            dir,
+            check,
+            ..
+        } => export(shell, options, output, dir, check),
+        Command::Watch { shell, options } => watch(shell, options),
         Command::Edit {} => edit(),
-        Command::Show {} => show(),
-        Command::Find { variable } => find(variable),
-        Command::Load { env_file } => load(env_file),
-        Command::Allow { env_file } => allow(env_file),
-        Command::Deny { env_file } => deny(env_file),
+        Command::Show { mask, files } => show(mask, files, color, opt.quiet),
+        Command::Find { variables } => find(variables),
+        Command::Which { variable } => which(variable),
+        Command::Load {
+            env_file,
+            command,
+            format,
+            into,
+        } => load(env_file, command, format, into),
+        Command::Allow {
+            note,
+            glob: Some(glob),
+            yes,
+            dry_run,
+            ..
+        } => allow_glob(glob, note, yes, dry_run),
+        Command::Allow {
+            env_file,
+            note,
+            show_values,
+            yes,
+            dry_run,
+            ..
+        } => allow(env_file, note, show_values, yes, dry_run),
+        Command::Refresh { env_file } => refresh(env_file),
+        Command::Deny {
+            env_file,
+            all,
+            yes,
+            dry_run,
+        } => deny(env_file, all, yes, dry_run),
         Command::Path {} => path(),
+        Command::Init { force } => init(force),
+        Command::Config(ConfigCommand::Validate {}) => validate(),
+        Command::Config(ConfigCommand::Explain {}) => explain(),
+        Command::Config(ConfigCommand::Get { key }) => config_get(key),
+        Command::Config(ConfigCommand::Set { key, value }) => config_set(key, value),
+        Command::Config(ConfigCommand::AddPath { pattern, env }) => add_path(pattern, env),
+        Command::Config(ConfigCommand::RemovePath { pattern }) => remove_path(pattern),
+        Command::Completions { shell } => {
+            Envy::clap().gen_completions_to("envy", shell, &mut io::stdout());
+            Ok(())
+        }
     }
 }
 
 /// Export all environment variables from the env file into the current shell
 /// The command is called load because `source` is reserved for potentially
 /// showing the source of an env variable in the future.
-fn load(env_file: PathBuf) -> Result<(), anyhow::Error> {
-    if !env_file.exists() {
+fn load(
+    env_file: PathBuf,
+    command: Option<String>,
+    format: Option<EnvFormat>,
+    shell: String,
+) -> Result<(), anyhow::Error> {
+    // Allow piping in variables, e.g. from a secret manager
+    let env_vars = if let Some(command) = command {
+        get_env_vars_from_command(&command, format.unwrap_or(EnvFormat::Dotenv))?
+    } else if env_file == Path::new("-") {
+        get_env_vars_from_reader(io::stdin().lock(), format.unwrap_or(EnvFormat::Dotenv))?
+    } else if env_file.exists() {
+        let format = format.unwrap_or_else(|| EnvFormat::from_path(&env_file));
+        get_env_vars_from_file_as(&env_file, format)?
+    } else {
         return Err(anyhow!("File does not exist: {}", env_file.display()));
     };
-    source(env_file)
+    let env_vars = check_env_keys(env_vars, false)?;
+    format_exports(&mut io::stdout().lock(), &env_vars, &shell, false)
 }
 
 /// Get all environment variables currently set
 /// and return the value of the given variable
-fn find(variable: String) -> Result<(), anyhow::Error> {
-    let value = std::env::vars()
-        .find(|(key, _)| key == &variable)
-        .map(|(_, value)| value);
+fn find(variables: Vec<String>) -> Result<(), anyhow::Error> {
+    let find_value = |variable: &String| {
+        std::env::vars()
+            .find(|(key, _)| key == variable)
+            .map(|(_, value)| value)
+    };
 
-    match value {
-        Some(value) => println!("{value}"),
-        None => println!("Variable {variable} not found"),
+    let mut found_all = true;
+    // Keep the plain output for a single variable, so existing scripts still work
+    if let [variable] = variables.as_slice() {
+        match find_value(variable) {
+            Some(value) => println!("{value}"),
+            None => {
+                println!("Variable {variable} not found");
+                found_all = false;
+            }
+        }
+    } else {
+        for variable in &variables {
+            match find_value(variable) {
+                Some(value) => println!("{variable}={value}"),
+                None => {
+                    println!("{variable} not found");
+                    found_all = false;
+                }
+            }
+        }
     }
 
+    // Allow using `find` in shell conditionals
+    if !found_all {
+        process::exit(1);
+    }
     Ok(())
 }
 
-fn deny(env_file: PathBuf) -> Result<()> {
-    if !env_file.exists() {
-        return Err(anyhow!("File does not exist: {}", env_file.display()));
-    };
+fn deny(env_file: PathBuf, all: bool, yes: bool, dry_run: bool) -> Result<()> {
+    if all {
+        return deny_all(yes, dry_run);
+    }
+    let env_file = dir_env_file(env_file);
     let mut settings = Settings::load(config_path()?)?;
-    // Get full path to env file
-    let env_file = env_file.canonicalize()?;
-    settings.remove_env(env_file);
+    let before = settings.env_paths();
+    // Globs are stored as they were given
+    settings.remove_env(env_file.clone());
+    // The file might be gone already, so also try the full path as given
+    settings.remove_env(std::path::absolute(&env_file)?);
+    if let Ok(env_file) = env_file.canonicalize() {
+        settings.remove_env(env_file.clone());
+    }
+    if settings.env_paths() == before {
+        return Err(anyhow!("{} is not allowed", env_file.display()));
+    }
+    if dry_run {
+        print_envs_diff(&before, &settings.env_paths());
+        return Ok(());
+    }
     Settings::save(config_path()?, settings)
 }
 
+/// Print where the variable that envy would export comes from,
+/// i.e. the source of its last definition
+fn which(variable: String) -> Result<()> {
+    let dir = current_dir()?;
+    let settings = Settings::load_layered(config_path()?, &dir)?;
+    let env_files = settings.matching_env_files(&dir);
+    let env_vars = collect_env_vars(&settings, &dir, &env_files, None)?;
+    // The last line that sets or unsets the variable decides, like in `export`
+    let source = env_vars.iter().rev().find_map(|(var, source)| {
+        if split_env_unset(var) == Some(variable.as_str()) {
+            return Some(None);
+        }
+        let (key, _) = split_env_append(var).or_else(|| split_env_var(var))?;
+        (key == variable).then_some(Some(source))
+    });
+    match source.flatten() {
+        Some(_) if !settings.is_exported(&variable) => {
+            eprintln!("{variable} is not exported because of the allowlist or blocklist");
+            process::exit(1);
+        }
+        Some(source) => println!("{source}"),
+        None => {
+            eprintln!("{variable} is not defined for this directory");
+            process::exit(1);
+        }
+    }
+    Ok(())
+}
+
+/// Revoke all allowed env files at once
+fn deny_all(yes: bool, dry_run: bool) -> Result<()> {
+    let mut settings = Settings::load(config_path()?)?;
+    let before = settings.env_paths();
+    settings.envs = None;
+    if dry_run {
+        print_envs_diff(&before, &[]);
+        return Ok(());
+    }
+    if before.is_empty() {
+        println!("No env files are allowed");
+        return Ok(());
+    }
+    let question = format!("Revoke all {} allowed env files?", before.len());
+    if !yes && io::stdin().is_terminal() && !confirm(&question)? {
+        println!("Nothing revoked");
+        return Ok(());
+    }
+    Settings::save(config_path()?, settings)
+}
+
+/// Print the env files that would be added to or removed from the allowed ones
+fn print_envs_diff(before: &[PathBuf], after: &[PathBuf]) {
+    let removed: Vec<_> = before.iter().filter(|env| !after.contains(env)).collect();
+    let added: Vec<_> = after.iter().filter(|env| !before.contains(env)).collect();
+    if removed.is_empty() && added.is_empty() {
+        println!("No changes");
+    }
+    for env in removed {
+        println!("- {}", env.display());
+    }
+    for env in added {
+        println!("+ {}", env.display());
+    }
+}
+
 // Add the current directory to the list of allowed paths.
 // The `.env` file will be loaded automatically on dir enter.
-fn allow(env_file: PathBuf) -> Result<()> {
+fn allow(
+    env_file: PathBuf,
+    note: Option<String>,
+    show_values: bool,
+    yes: bool,
+    dry_run: bool,
+) -> Result<()> {
+    let env_file = dir_env_file(env_file);
     if !env_file.exists() {
         return Err(anyhow!("File does not exist: {}", env_file.display()));
     };
+    // Show what is about to be trusted before adding it
+    if env_file.ends_with(PROJECT_CONFIG_NAME) {
+        println!(
+            "`{}` is a project config, its patterns and env files get merged into yours",
+            env_file.display()
+        );
+    } else {
+        println!("`{}` sets the following variables:", env_file.display());
+        for var in get_env_vars_from_file(&env_file)? {
+            if let Some((key, value)) = split_env_var(&var) {
+                if show_values {
+                    println!("  {key}={value}");
+                } else {
+                    println!("  {key}");
+                }
+            }
+        }
+    }
+    if !yes && !dry_run && io::stdin().is_terminal() && !confirm("Allow envy to load this file?")? {
+        println!("Not allowed");
+        return Ok(());
+    }
     let mut settings = Settings::load(config_path()?)?;
     // Get full path to env file
     let env_file = env_file.canonicalize()?;
-    settings.add_env(env_file);
+    let before = settings.env_paths();
+    settings.add_env(env_file, note);
+    if dry_run {
+        print_envs_diff(&before, &settings.env_paths());
+        return Ok(());
+    }
+    Settings::save(config_path()?, settings)
+}
+
+/// Get the `.env` file of a directory like `.`, or the given path otherwise
+fn dir_env_file(path: PathBuf) -> PathBuf {
+    if path.is_dir() {
+        path.join(".env")
+    } else {
+        path
+    }
+}
+
+/// Renew the trust in an already allowed env file, e.g. after editing it,
+/// so that it doesn't expire with `trust_ttl_days`
+fn refresh(env_file: PathBuf) -> Result<()> {
+    let env_file = dir_env_file(env_file);
+    let env_file = env_file
+        .canonicalize()
+        .with_context(|| format!("File does not exist: {}", env_file.display()))?;
+    let mut settings = Settings::load(config_path()?)?;
+    if !settings.env_paths().contains(&env_file) {
+        return Err(anyhow!(
+            "{} is not allowed yet, use `envy allow` first",
+            env_file.display()
+        ));
+    }
+    settings.add_env(env_file, None);
     Settings::save(config_path()?, settings)
 }
 
+/// Allow all env files matching a glob, including ones that don't exist yet
+fn allow_glob(glob: String, note: Option<String>, yes: bool, dry_run: bool) -> Result<()> {
+    if !settings::expand_home(&glob).is_absolute() {
+        return Err(anyhow!("Glob is not an absolute path: {glob}"));
+    }
+    let question = format!("Allow envy to load all files matching `{glob}`?");
+    if !yes && !dry_run && io::stdin().is_terminal() && !confirm(&question)? {
+        println!("Not allowed");
+        return Ok(());
+    }
+    let mut settings = Settings::load(config_path()?)?;
+    let before = settings.env_paths();
+    settings.add_env_glob(glob, note);
+    if dry_run {
+        print_envs_diff(&before, &settings.env_paths());
+        return Ok(());
+    }
+    Settings::save(config_path()?, settings)
+}
+
+/// Ask a yes/no question on stdin, defaulting to no
+fn confirm(question: &str) -> Result<bool> {
+    print!("{question} [y/N] ");
+    io::stdout().flush()?;
+    let mut answer = String::new();
+    io::stdin().read_line(&mut answer)?;
+    Ok(matches!(answer.trim().to_lowercase().as_str(), "y" | "yes"))
+}
+
+#[cfg(windows)]
+const DEFAULT_EDITOR: &str = "notepad";
+#[cfg(not(windows))]
+const DEFAULT_EDITOR: &str = "vi";
+
 pub fn open_editor(filename: &str) -> Result<std::process::ExitStatus> {
-    let editor_name = std::env::var("EDITOR")?;
-    let mut editor = process::Command::new(editor_name).arg(filename).spawn()?;
+    let editor_name = ["EDITOR", "VISUAL"]
+        .iter()
+        .filter_map(|name| env::var(name).ok())
+        .find(|editor| !editor.is_empty());
+    let mut editor = match &editor_name {
+        Some(editor_name) => process::Command::new(editor_name)
+            .arg(filename)
+            .spawn()
+            .with_context(|| format!("Cannot start editor `{editor_name}`"))?,
+        None => process::Command::new(DEFAULT_EDITOR)
+            .arg(filename)
+            .spawn()
+            .context(
+                "Set the EDITOR environment variable to use `envy edit` (e.g. `export EDITOR=vim`)",
+            )?,
+    };
     Ok(editor.wait()?)
 }
 
 fn edit() -> Result<()> {
     let config = config_path()?;
+    Settings::migrate_legacy_config(&config)?;
+    // Start from the commented example instead of an empty file
+    if !config.exists() {
+        write_config_template(&config)?;
+    }
     open_editor(&config.to_string_lossy())?;
     Ok(())
 }
 
-fn hook(shell: String) -> Result<()> {
-    let hook = match shell.as_ref() {
-        "bash" => hooks::bash::Bash::hook()?,
-        "fish" => hooks::fish::Fish::hook()?,
-        "zsh" => Zsh::hook()?,
-        _ => return Err(anyhow!("{} is currently not supported", shell)),
+fn hook(shell: Option<String>, print_path: bool) -> Result<()> {
+    // Help debugging installs where the hook calls the wrong binary
+    if print_path {
+        println!("Hook path: {}", hooks::self_path()?);
+        println!("Executable: {}", env::current_exe()?.display());
+        return Ok(());
+    }
+    // The config is optional here, so that the hook works on a fresh install
+    let settings = Settings::load(config_path()?).ok();
+    let shell = resolve_shell(shell, settings.as_ref());
+    let hook = match shell.parse()? {
+        Shell::Bash => hooks::bash::Bash::hook()?,
+        Shell::Fish => hooks::fish::Fish::hook()?,
+        Shell::Tcsh | Shell::Csh => hooks::tcsh::Tcsh::hook()?,
+        Shell::Xonsh => hooks::xonsh::Xonsh::hook()?,
+        Shell::Zsh => Zsh::hook()?,
     };
     println!("{hook}");
     Ok(())
 }
 
-/// Get all environment variables from the given file
-fn get_env_vars_from_file(env: &Path) -> Result<Vec<String>> {
-    let mut env_vars = Vec::new();
-    let env = fs::read_to_string(env).context("Cannot read env file")?;
-    for line in env.lines() {
-        // Ignore comments
-        if line.starts_with('#') {
-            continue;
-        }
-        env_vars.push(line.to_string())
-    }
-    Ok(env_vars)
+/// Get the shell to use if none was given on the command line
+fn resolve_shell(shell: Option<String>, settings: Option<&EnvySettings>) -> String {
+    shell
+        .or_else(|| settings.and_then(|settings| settings.default_shell.clone()))
+        .unwrap_or_else(|| Shell::detect().name().to_string())
 }
 
-fn show() -> Result<()> {
-    let settings = Settings::load(config_path()?)?;
+fn show(mask: bool, files: Vec<PathBuf>, color: bool, quiet: bool) -> Result<()> {
     let dir = current_dir()?;
-    let env_files = settings.matching_env_files(&dir);
+    let settings = Settings::load_layered(config_path()?, &dir)?;
+    let mask = mask || settings.mask.unwrap_or(false);
+    let style = |style: Style| if color { style } else { Style::new() };
+    let (header, key_style, value_style) = (
+        style(Colour::Blue.bold()),
+        style(Colour::Cyan.normal()),
+        style(Colour::Green.normal()),
+    );
+    let print_var = |var: &str| match (split_env_var(var), var.split_once('=')) {
+        (Some((key, _)), _) if mask && settings.is_secret(key) => {
+            println!("{}={}", key_style.paint(key), value_style.paint("****"))
+        }
+        (_, Some((key, value))) => {
+            println!("{}={}", key_style.paint(key), value_style.paint(value))
+        }
+        _ => println!("{var}"),
+    };
+
+    warn_unchecked_files(&files);
+    let env_files = env_files(&settings, &dir, &files)?;
     for file in &env_files {
-        println!("Loaded from `{}`:", file.display());
+        if !quiet {
+            let title = format!("Loaded from `{}`:", file.display());
+            println!("{}", header.paint(title));
+        }
         let vars = get_env_vars_from_file(file).context("Cannot read env file")?;
         for var in vars {
-            println!("{var}");
+            print_var(&var);
+        }
+        if !quiet {
+            println!();
         }
-        println!();
     }
-    match settings.matching_patterns(&dir) {
-        Some(env) => println!("{}", env.join("\n")),
-        None => {
-            if env_files.is_empty() {
-                println!("envy found no pattern matches for this directory.");
+    match settings.matching_pattern_config(&dir) {
+        Some(path) => {
+            if !quiet {
+                let title = format!("Matched pattern `{}`:", path.pattern);
+                println!("{}", header.paint(title));
             }
+            let env = settings.pattern_env_vars(path)?;
+            env.iter().for_each(|var| print_var(var));
+        }
+        None if quiet => {}
+        None => println!("envy found no pattern matches for this directory."),
+    };
+
+    Ok(())
+}
+
+// Create a config file with a commented example
+fn init(force: bool) -> Result<()> {
+    let config = config_path()?;
+    if config.exists() && !force {
+        return Err(anyhow!(
+            "Config file already exists: {} (use --force to overwrite it)",
+            config.display()
+        ));
+    }
+    write_config_template(&config)?;
+    println!("Created config file at {}", config.display());
+    Ok(())
+}
+
+fn write_config_template(config: &Path) -> Result<()> {
+    if let Some(parent) = config.parent() {
+        fs::create_dir_all(parent).context("Cannot create config directory")?;
+    }
+    fs::write(config, CONFIG_TEMPLATE).context("Cannot write config")
+}
+
+// Report all problems in the config file
+fn validate() -> Result<()> {
+    let config = config_path()?;
+    let problems = Settings::validate(config.clone())?;
+    if problems.is_empty() {
+        println!("`{}` is valid", config.display());
+        return Ok(());
+    }
+    for problem in &problems {
+        println!("{problem}");
+    }
+    Err(anyhow!(
+        "Found {} problem(s) in `{}`",
+        problems.len(),
+        config.display()
+    ))
+}
+
+/// Print the settings as envy understands them
+fn explain() -> Result<()> {
+    let config = config_path()?;
+    let settings = Settings::load(config.clone())?;
+    println!("Config file: {}", config.display());
+
+    let envs = settings.envs.as_deref().unwrap_or_default();
+    println!("Allowed env files: {}", envs.len());
+    for env in envs {
+        match env.note() {
+            Some(note) => println!("  {} ({note})", env.path().display()),
+            None => println!("  {}", env.path().display()),
         }
+    }
+    if let Some(ttl_days) = settings.trust_ttl_days {
+        println!("Allowed env files expire after {ttl_days} days");
+    }
+    println!(
+        "Load parent env files (source_up): {}",
+        settings.source_up.unwrap_or(false)
+    );
+    let list = |names: &Option<Vec<String>>| match names {
+        Some(names) => names.join(", "),
+        None => "-".to_string(),
     };
+    println!("Allowlist: {}", list(&settings.allowlist));
+    println!("Blocklist: {}", list(&settings.blocklist));
+
+    let paths = settings.paths.as_deref().unwrap_or_default();
+    println!("Patterns: {} (the first match wins)", paths.len());
+    for (i, path) in paths.iter().enumerate() {
+        let vars = path.env.iter().filter(|var| is_env_var(var)).count();
+        print!("  {}. `{}` with {vars} variable(s)", i + 1, path.pattern);
+        match &path.env_file {
+            Some(env_file) => println!(" and `{}`", settings.config_dir.join(env_file).display()),
+            None => println!(),
+        }
+    }
 
+    let profiles = settings.profiles.iter().flatten();
+    println!("Profiles: {}", profiles.clone().count());
+    for (name, profile) in profiles {
+        let vars = profile.env.iter().filter(|var| is_env_var(var)).count();
+        let default = settings.default_profile.as_deref() == Some(name.as_str());
+        print!(
+            "  `{name}`{} with {vars} variable(s)",
+            if default { " (default)" } else { "" }
+        );
+        match &profile.env_file {
+            Some(env_file) => println!(" and `{}`", settings.config_dir.join(env_file).display()),
+            None => println!(),
+        }
+    }
+    Ok(())
+}
+
+/// Print the value of a single setting, or the whole config
+fn config_get(key: Option<String>) -> Result<()> {
+    let settings = Settings::load(config_path()?)?;
+    let Some(key) = key else {
+        print!(
+            "{}",
+            toml::to_string_pretty(&settings).context("Cannot serialize config")?
+        );
+        return Ok(());
+    };
+    let table = toml::Table::try_from(&settings).context("Cannot serialize config")?;
+    match table.get(&key) {
+        // Print strings without quotes, so that they can be used in scripts
+        Some(toml::Value::String(value)) => println!("{value}"),
+        Some(value) => println!("{value}"),
+        None => return Err(anyhow!("{key} is not set")),
+    }
     Ok(())
 }
 
+/// Change a single setting and save the config
+fn config_set(key: String, value: String) -> Result<()> {
+    let config = config_path()?;
+    let settings = Settings::load(config.clone())?;
+    let value = match format!("value = {value}").parse::<toml::Table>() {
+        Ok(mut table) => table.remove("value").unwrap_or(toml::Value::String(value)),
+        Err(_) => toml::Value::String(value),
+    };
+    let mut table = toml::Table::try_from(&settings).context("Cannot serialize config")?;
+    table.insert(key.clone(), value);
+    let mut new_settings: EnvySettings = table
+        .try_into()
+        .with_context(|| format!("Invalid value for {key}"))?;
+    // Unknown keys get dropped when deserializing
+    let known = toml::Table::try_from(&new_settings).context("Cannot serialize config")?;
+    if !known.contains_key(&key) {
+        return Err(anyhow!("Unknown setting: {key}"));
+    }
+    new_settings.config_dir = settings.config_dir;
+    Settings::save(config, new_settings)
+}
+
+/// Add a pattern to the config
+fn add_path(pattern: String, env: Vec<String>) -> Result<()> {
+    let config = config_path()?;
+    let mut settings = Settings::load(config.clone())?;
+    settings.add_pattern(&pattern, env)?;
+    Settings::save(config, settings)
+}
+
+/// Remove a pattern from the config
+fn remove_path(pattern: String) -> Result<()> {
+    let config = config_path()?;
+    let mut settings = Settings::load(config.clone())?;
+    if !settings.remove_pattern(&pattern) {
+        return Err(anyhow!("Pattern not found in config: {pattern}"));
+    }
+    Settings::save(config, settings)
+}
+
 fn path() -> Result<()> {
     println!(
         "{}",
@@ -152,56 +652,976 @@ fn path() -> Result<()> {
     Ok(())
 }
 
-/// Source the given env file
-/// This will print the commands to stdout that need to be executed to source
-/// the file
+/// Source the given env vars
+/// This will write the commands that need to be executed to source the vars
 ///
 /// This is used by the `envy export` command to source all matching env files
 /// and by `envy load` to source the given env file directly (for the current
 /// session)
-fn source(env_file: PathBuf) -> Result<()> {
-    for var in get_env_vars_from_file(&env_file)? {
-        if var.starts_with("export") {
-            println!("{var}");
-            continue;
+fn source(out: &mut dyn Write, env_vars: &[String]) -> Result<()> {
+    for var in env_vars {
+        if let Some((key, value)) = split_env_var_raw(var) {
+            writeln!(out, "export {key}={}", shell_value(value))?;
+        } else if let Some(key) = split_env_unset(var) {
+            writeln!(out, "unset {key}")?;
         }
+    }
+    Ok(())
+}
 
-        println!("export {var}");
+/// Write plain assignments for the given env vars, e.g. for a function scope
+fn assign(out: &mut dyn Write, env_vars: &[String]) -> Result<()> {
+    for var in env_vars {
+        if let Some((key, value)) = split_env_var_raw(var) {
+            writeln!(out, "{key}={}", shell_value(value))?;
+        } else if let Some(key) = split_env_unset(var) {
+            writeln!(out, "unset {key}")?;
+        }
     }
     Ok(())
 }
 
-fn export(shell: String) -> Result<()> {
-    let settings = Settings::load(config_path()?)?;
-    match shell.as_ref() {
-        "bash" | "zsh" => {
-            if let Some(patterns) = settings.matching_patterns(&current_dir()?) {
-                println!("export {}", patterns.join(" "));
+/// Write the env vars as `KEY=value` records, each terminated by a NUL byte
+///
+/// Values are written without quotes and can contain newlines, since NUL
+/// bytes can't be part of an environment variable.
+fn export_null(out: &mut dyn Write, env_vars: &[String]) -> Result<()> {
+    // There is no record for removing a variable, so leave out unset ones
+    let mut records = Vec::new();
+    for var in env_vars {
+        if let Some((key, value)) = split_env_var(var) {
+            records.push((key, value));
+        } else if let Some(key) = split_env_unset(var) {
+            records.retain(|(k, _)| *k != key);
+        }
+    }
+    for (key, value) in records {
+        write!(out, "{key}={value}\0")?;
+    }
+    Ok(())
+}
+
+/// Write the env vars with base64-encoded values that get decoded on evaluation
+///
+/// Values are passed on literally (without expanding variables in double
+/// quotes), so newlines and shell metacharacters can't break the output.
+/// The command substitution strips trailing newlines, though.
+/// e.g. `export FOO="$(printf %s YmFy | base64 -d)"`
+fn export_base64(out: &mut dyn Write, env_vars: &[String], local: bool) -> Result<()> {
+    let export = if local { "" } else { "export " };
+    for var in env_vars {
+        if let Some((key, value)) = split_env_var(var) {
+            let value = BASE64.encode(value);
+            writeln!(out, "{export}{key}=\"$(printf %s {value} | base64 -d)\"")?;
+        } else if let Some(key) = split_env_unset(var) {
+            writeln!(out, "unset {key}")?;
+        }
+    }
+    Ok(())
+}
+
+/// Get a value as written in an env file in a form that shells can evaluate
+///
+/// Quoted values are passed on as they are, because all supported shells
+/// interpret quotes similarly. Unquoted values containing whitespace get
+/// double-quoted, so they are not split into multiple words.
+fn shell_value(value: &str) -> Cow<'_, str> {
+    if !value.starts_with(['"', '\'']) && value.contains(char::is_whitespace) {
+        Cow::Owned(format!("\"{value}\""))
+    } else {
+        Cow::Borrowed(value)
+    }
+}
+
+/// Write the `set` commands for the given env vars
+///
+/// fish needs to be told to export each variable individually
+/// e.g. "set -gx FOO bar"
+fn export_fish(out: &mut dyn Write, env_vars: &[String]) -> Result<()> {
+    for var in env_vars {
+        if let Some((key, value)) = split_env_var_raw(var) {
+            writeln!(out, "set -gx {key} {}", shell_value(value))?;
+        } else if let Some(key) = split_env_unset(var) {
+            writeln!(out, "set -e {key}")?;
+        }
+    }
+    Ok(())
+}
+
+/// Write the `setenv` commands for the given env vars
+///
+/// The output of `export tcsh` is evaluated from backticks, which joins all
+/// lines into one, so every command gets terminated with a semicolon.
+fn export_tcsh(out: &mut dyn Write, env_vars: &[String]) -> Result<()> {
+    for var in env_vars {
+        if let Some((key, value)) = split_env_var_raw(var) {
+            writeln!(out, "setenv {key} {};", shell_value(value))?;
+        } else if let Some(key) = split_env_unset(var) {
+            writeln!(out, "unsetenv {key};")?;
+        }
+    }
+    Ok(())
+}
+
+/// Write the env vars as xonsh (i.e. Python) assignments
+///
+/// Values are written as string literals, so variables in them are not
+/// expanded. Removing a variable that isn't set is not an error.
+/// e.g. `$FOO = "bar"`
+fn export_xonsh(out: &mut dyn Write, env_vars: &[String]) -> Result<()> {
+    for var in env_vars {
+        if let Some((key, value)) = split_env_var(var) {
+            // A JSON string is a valid Python string literal as well
+            writeln!(out, "${key} = {}", serde_json::to_string(value)?)?;
+        } else if let Some(key) = split_env_unset(var) {
+            writeln!(out, "${{...}}.pop({key:?}, None)")?;
+        }
+    }
+    Ok(())
+}
+
+/// Write the env vars as a JSON object, e.g. for editor integrations
+///
+/// With sources, each variable maps to an object with its value and
+/// the env file or pattern it comes from.
+fn export_json(
+    out: &mut dyn Write,
+    env_vars: &[String],
+    sources: Option<&BTreeMap<String, String>>,
+) -> Result<()> {
+    let mut object = serde_json::Map::new();
+    for var in env_vars {
+        if let Some((key, value)) = split_env_var(var) {
+            let value = match sources {
+                Some(sources) => json!({ "value": value, "source": sources.get(key) }),
+                None => json!(value),
+            };
+            object.insert(key.to_string(), value);
+        } else if let Some(key) = split_env_unset(var) {
+            object.shift_remove(key);
+        }
+    }
+    serde_json::to_writer_pretty(&mut *out, &object)?;
+    writeln!(out)?;
+    Ok(())
+}
+
+fn export(
+    shell: Option<String>,
+    options: ExportOptions,
+    output_file: Option<PathBuf>,
+    dir: Option<PathBuf>,
+    check: bool,
+) -> Result<()> {
+    // Allow turning envy off temporarily without removing the hook
+    if env::var_os("ENVY_DISABLE").is_some_and(|value| !value.is_empty() && value != "0") {
+        return Ok(());
+    }
+    // The hooks set `ENVY_EXPORTING`, so anything else that doesn't print to a
+    // terminal is probably a redirect that writes the values to a file
+    if output_file.is_none()
+        && !check
+        && !options.diff
+        && !io::stdout().is_terminal()
+        && env::var_os("ENVY_EXPORTING").is_none()
+    {
+        eprintln!(
+            "envy: Warning: writing plaintext values to a file or pipe instead of a shell hook"
+        );
+    }
+    // The hook of an already running shell stays the same after an upgrade
+    if let Some(version) = env::var_os("ENVY_HOOK_VERSION") {
+        if version != env!("CARGO_PKG_VERSION") {
+            eprintln!(
+                "envy: Warning: the shell hook is from envy {}, but this is envy {}. Open a new shell to update it.",
+                version.to_string_lossy(),
+                env!("CARGO_PKG_VERSION")
+            );
+        }
+    }
+    let config = config_path()?;
+    let dir = match dir {
+        Some(dir) => dir
+            .canonicalize()
+            .with_context(|| format!("Directory does not exist: {}", dir.display()))?,
+        None => current_dir()?,
+    };
+    warn_unchecked_files(&options.files);
+    if check {
+        return check_export(config, &dir, &options);
+    }
+    let cache = Cache::new(&config, &dir)?;
+    // The output depends on the current environment, which the cache doesn't track
+    if !options.print_changed_only && !options.no_inherit && !options.diff {
+        if let Some(output) = cache.get(&config) {
+            return write_output(&output, output_file.as_deref());
+        }
+    }
+
+    let settings = Settings::load_layered(config.clone(), &dir)?;
+    let shell = resolve_shell(shell, Some(&settings));
+    let (output, env_keys) = export_env(&settings, &dir, &shell, &options)?;
+    write_output(&output, output_file.as_deref())?;
+    // Allowed files can expire without any of the inputs changing
+    if options.print_changed_only
+        || options.no_inherit
+        || options.diff
+        || settings.trust_ttl_days.is_some()
+    {
+        return Ok(());
+    }
+
+    // The cache is only an optimization, so failing to write it is not fatal
+    let mut inputs = [
+        env_files(&settings, &dir, &options.files)?,
+        settings.pattern_env_files(),
+    ]
+    .concat();
+    inputs.extend(get_included_env_files(&inputs));
+    inputs.extend(get_watched_files(&inputs));
+    inputs.extend(settings.project_config.clone());
+    inputs.extend(settings.env_file_candidates(&dir));
+    inputs.extend(
+        options
+            .template
+            .iter()
+            .filter_map(|t| t.canonicalize().ok()),
+    );
+    let _ = cache.set(&config, &inputs, &env_keys, &output);
+    Ok(())
+}
+
+/// Resolve the variables for the directory like `export`, but only print a
+/// summary without any values to stderr, e.g. for CI logs
+fn check_export(config: PathBuf, dir: &Path, options: &ExportOptions) -> Result<()> {
+    let settings = Settings::load_layered(config, dir)?;
+    let env_files = env_files(&settings, dir, &options.files)?;
+    let env_vars = collect_env_vars(&settings, dir, &env_files, options.profile.as_deref())?;
+    let mut problems = Vec::new();
+    let mut definitions: BTreeMap<&str, usize> = BTreeMap::new();
+    for (var, source) in &env_vars {
+        let key = split_env_append(var)
+            .map(|(key, _)| key)
+            .or_else(|| env_key(var));
+        match key {
+            Some(key) if is_valid_env_key(key) => *definitions.entry(key).or_default() += 1,
+            Some(key) => problems.push(format!("Invalid variable name {key} in {source}")),
+            // The line might contain a secret, so don't print it
+            None => problems.push(format!("Line without `=` in {source}")),
+        }
+    }
+    for problem in &problems {
+        eprintln!("envy: {problem}");
+    }
+    if !problems.is_empty() {
+        return Err(anyhow!("Found {} problem(s)", problems.len()));
+    }
+    for (key, count) in &definitions {
+        if *count > 1 {
+            eprintln!("envy: Warning: {key} is defined {count} times, the last one wins");
+        }
+    }
+    // Run the rest of the export as well, e.g. to catch an unreadable template
+    export_env(&settings, dir, "json", options)?;
+    eprintln!(
+        "envy: {} variable(s) from {} env file(s) resolve cleanly",
+        definitions.len(),
+        env_files.len()
+    );
+    Ok(())
+}
+
+/// Print the output of `export` or write it to the given file
+fn write_output(output: &str, path: Option<&Path>) -> Result<()> {
+    let Some(path) = path else {
+        print!("{output}");
+        return Ok(());
+    };
+    write_private(path, output.as_bytes())
+        .with_context(|| format!("Cannot write {}", path.display()))
+}
+
+/// Write a file that contains the values of exported variables,
+/// so that it is only readable by the current user.
+/// The mode of `OpenOptions` only applies to new files, so the permissions
+/// of an existing file are restricted as well.
+pub(crate) fn write_private(path: &Path, bytes: &[u8]) -> io::Result<()> {
+    let mut options = fs::OpenOptions::new();
+    options.write(true).create(true).truncate(true);
+    #[cfg(unix)]
+    std::os::unix::fs::OpenOptionsExt::mode(&mut options, 0o600);
+    let mut file = options.open(path)?;
+    #[cfg(unix)]
+    file.set_permissions(std::os::unix::fs::PermissionsExt::from_mode(0o600))?;
+    file.write_all(bytes)
+}
+
+/// Get the commands that export all env vars for the given directory,
+/// along with the names of the variables from the current environment
+/// that the output depends on
+fn export_env(
+    settings: &EnvySettings,
+    dir: &Path,
+    shell: &str,
+    options: &ExportOptions,
+) -> Result<(String, Vec<String>)> {
+    let env_files = env_files(settings, dir, &options.files)?;
+    let mut all_env_vars = collect_env_vars(settings, dir, &env_files, options.profile.as_deref())?;
+    if let Some(defaults) = settings.defaults()? {
+        interpolate(&mut all_env_vars, &defaults);
+    }
+    let separator = settings.append_separator.as_deref().unwrap_or(":");
+    let mut env_keys = resolve_appends(&mut all_env_vars, separator);
+    if let Some(path) = settings.matching_pattern_config(dir) {
+        let path_add = path.path_add.as_deref().unwrap_or_default();
+        if !path_add.is_empty() {
+            let (var, from_env) = prepend_path(&all_env_vars, path_add, dir)?;
+            if from_env {
+                env_keys.push("PATH".to_string());
             }
-            for env_file in settings.matching_env_files(&current_dir()?) {
-                source(env_file)?
+            all_env_vars.push((var, format!("pattern {}", path.pattern)));
+        }
+    }
+    all_env_vars.retain(|(var, _)| match env_key(var) {
+        Some(key) => settings.is_exported(key) && options.allows(key),
+        None => true,
+    });
+    if let Some(prefix) = &options.prefix {
+        for (var, _) in &mut all_env_vars {
+            if let Some((key, value)) = split_env_var_raw(var) {
+                if !key.starts_with(prefix.as_str()) {
+                    *var = format!("{prefix}{key}={value}");
+                }
+            } else if let Some(key) = split_env_unset(var) {
+                if !key.starts_with(prefix.as_str()) {
+                    *var = format!("unset {prefix}{key}");
+                }
             }
         }
-        "fish" => {
-            if let Some(patterns) = settings.matching_patterns(&current_dir()?) {
-                // patterns is a vec of environment variables, which need to be exported
-                // e.g. ["FOO=bar", "BAR=baz"]
-                // fish needs to be told to export each variable individually
-                // e.g. "set -gx FOO bar"
-                for pattern in patterns {
-                    if let Some((var, value)) = pattern.split_once('=') {
-                        println!("set -gx {var} {value}");
-                    }
+    }
+    // Later definitions of a variable override earlier ones
+    let mut sources = BTreeMap::new();
+    for (var, source) in &all_env_vars {
+        if let Some((key, _)) = split_env_var(var) {
+            sources.insert(key.to_string(), source.clone());
+        }
+    }
+    let all_env_vars: Vec<String> = all_env_vars.into_iter().map(|(var, _)| var).collect();
+
+    let mut all_env_vars = check_env_keys(all_env_vars, settings.strict_keys.unwrap_or(false))?;
+    warn_case_collisions(&all_env_vars);
+    if let Some(filter) = &options.env_filter {
+        all_env_vars.retain(|var| env_key(var).is_some_and(|key| filter.is_match(key)));
+    }
+    if options.print_changed_only {
+        retain_changed(&mut all_env_vars);
+    }
+    if options.sort {
+        // The sort is stable, so later definitions of a variable still win
+        all_env_vars.sort_by(|a, b| env_key(a).cmp(&env_key(b)));
+    }
+    if options.no_inherit {
+        let inherited = inherited_env_keys(&all_env_vars);
+        all_env_vars.splice(0..0, inherited.iter().map(|key| format!("unset {key}")));
+    }
+    if options.diff {
+        print_env_diff(&all_env_vars);
+        return Ok((String::new(), env_keys));
+    }
+    let mut output = Vec::new();
+    // The template replaces the commands of the shell
+    if let Some(template) = &options.template {
+        render_template(
+            &mut output,
+            template,
+            &all_env_vars,
+            options.allow_undefined,
+        )?;
+        return Ok((String::from_utf8(output)?, env_keys));
+    }
+    match shell {
+        _ if options.null => export_null(&mut output, &all_env_vars)?,
+        "json" if options.with_source => export_json(&mut output, &all_env_vars, Some(&sources))?,
+        _ if options.with_source => {
+            return Err(anyhow!("--with-source is only supported for json"))
+        }
+        "bash" | "zsh" if options.eval_safe => {
+            export_base64(&mut output, &all_env_vars, options.local)?
+        }
+        _ if options.eval_safe => {
+            return Err(anyhow!("--eval-safe is only supported for bash and zsh"))
+        }
+        _ => format_exports(&mut output, &all_env_vars, shell, options.local)?,
+    }
+    Ok((String::from_utf8(output)?, env_keys))
+}
+
+/// Write the template with all `{{KEY}}` placeholders replaced by the values
+/// of the env vars, e.g. to generate a config file
+///
+/// Placeholders of undefined variables are an error, unless they are allowed,
+/// in which case they are replaced with nothing.
+fn render_template(
+    out: &mut dyn Write,
+    template: &Path,
+    env_vars: &[String],
+    allow_undefined: bool,
+) -> Result<()> {
+    let content = fs::read_to_string(template)
+        .with_context(|| format!("Cannot read template {}", template.display()))?;
+    let mut values = BTreeMap::new();
+    for var in env_vars {
+        if let Some((key, value)) = split_env_var(var) {
+            values.insert(key, value);
+        } else if let Some(key) = split_env_unset(var) {
+            values.remove(key);
+        }
+    }
+    let mut rendered = String::new();
+    let mut undefined = Vec::new();
+    let mut rest = content.as_str();
+    while let Some(start) = rest.find("{{") {
+        let Some(end) = rest[start..].find("}}") else {
+            break;
+        };
+        let key = rest[start + 2..start + end].trim();
+        rendered.push_str(&rest[..start]);
+        match values.get(key) {
+            Some(value) => rendered.push_str(value),
+            None if !undefined.contains(&key) => undefined.push(key),
+            None => {}
+        }
+        rest = &rest[start + end + 2..];
+    }
+    rendered.push_str(rest);
+    if !allow_undefined && !undefined.is_empty() {
+        return Err(anyhow!(
+            "Undefined variables in template {}: {}",
+            template.display(),
+            undefined.join(", ")
+        ));
+    }
+    out.write_all(rendered.as_bytes())?;
+    Ok(())
+}
+
+/// Replace references like `${KEY}` in the values with the value of `KEY`
+/// from the defaults or an earlier definition.
+/// Single-quoted values are taken literally, and unknown references are
+/// left for the shell to expand.
+fn interpolate(env_vars: &mut [(String, String)], defaults: &[String]) {
+    let mut scope: BTreeMap<String, String> = defaults
+        .iter()
+        .filter_map(|var| split_env_var(var))
+        .map(|(key, value)| (key.to_string(), value.to_string()))
+        .collect();
+    for (var, _) in env_vars {
+        let Some((key, value)) = split_env_var_raw(var) else {
+            continue;
+        };
+        if !value.starts_with('\'') {
+            *var = format!("{key}={}", substitute(value, &scope));
+        }
+        if let Some((key, value)) = split_env_var(var) {
+            scope.insert(key.to_string(), value.to_string());
+        }
+    }
+}
+
+/// Replace all `${KEY}` in the value whose key is in the scope
+fn substitute(value: &str, scope: &BTreeMap<String, String>) -> String {
+    let mut result = String::new();
+    let mut rest = value;
+    while let Some(start) = rest.find("${") {
+        let Some(end) = rest[start..].find('}') else {
+            break;
+        };
+        let key = &rest[start + 2..start + end];
+        result.push_str(&rest[..start]);
+        match scope.get(key) {
+            Some(value) => result.push_str(value),
+            None => result.push_str(&rest[start..=start + end]),
+        }
+        rest = &rest[start + end + 1..];
+    }
+    result.push_str(rest);
+    result
+}
+
+/// Replace appends like `PATH+=/opt/bin` with the joined value of the
+/// variable from earlier definitions or the current environment.
+/// Values that are already part of it aren't added again, so that exporting
+/// repeatedly from the hook doesn't keep growing the variable.
+/// Returns the names of the variables taken from the environment.
+fn resolve_appends(env_vars: &mut [(String, String)], separator: &str) -> Vec<String> {
+    let mut env_keys = Vec::new();
+    for i in 0..env_vars.len() {
+        let Some((key, value)) = split_env_append(&env_vars[i].0) else {
+            continue;
+        };
+        let (key, value) = (key.to_string(), value.to_string());
+        let earlier = env_vars[..i].iter().rev().find_map(|(var, _)| {
+            split_env_var(var)
+                .filter(|(k, _)| *k == key)
+                .map(|(_, v)| v.to_string())
+        });
+        let base = earlier.or_else(|| {
+            env_keys.push(key.clone());
+            env::var(&key).ok()
+        });
+        let joined = match base {
+            Some(base) if base.split(separator).any(|part| part == value) => base,
+            Some(base) if !base.is_empty() => format!("{base}{separator}{value}"),
+            _ => value,
+        };
+        env_vars[i].0 = format!("{key}={}", quote_literal(&joined));
+    }
+    env_keys
+}
+
+/// Put the given directories in front of the final `PATH`, moving them there
+/// if they are already part of it, so that exporting repeatedly from the hook
+/// doesn't keep growing it.
+/// Returns the new definition of `PATH` and whether it is based on the
+/// environment rather than an earlier definition.
+fn prepend_path(
+    env_vars: &[(String, String)],
+    dirs: &[PathBuf],
+    dir: &Path,
+) -> Result<(String, bool)> {
+    let earlier = env_vars
+        .iter()
+        .rev()
+        .find_map(|(var, _)| match split_env_var(var) {
+            Some(("PATH", value)) => Some(value.to_string()),
+            _ => None,
+        });
+    let from_env = earlier.is_none();
+    let base = earlier
+        .or_else(|| env::var("PATH").ok())
+        .unwrap_or_default();
+    let dirs: Vec<PathBuf> = dirs
+        .iter()
+        .map(|path| dir.join(settings::expand_home(&path.to_string_lossy())))
+        .collect();
+    let rest = env::split_paths(&base).filter(|path| !dirs.contains(path));
+    let joined = env::join_paths(dirs.iter().cloned().chain(rest))
+        .context("Cannot add a directory containing the path separator to PATH")?;
+    let var = format!("PATH={}", quote_literal(&joined.to_string_lossy()));
+    Ok((var, from_env))
+}
+
+/// Quote a literal value, unless it only consists of characters that
+/// no shell interprets (e.g. `/usr/bin:/opt/bin`)
+fn quote_literal(value: &str) -> Cow<'_, str> {
+    let is_plain = |c: char| c.is_ascii_alphanumeric() || "_-/:.,=@%+".contains(c);
+    if value.chars().all(is_plain) {
+        Cow::Borrowed(value)
+    } else if !value.contains('\'') {
+        Cow::Owned(format!("'{value}'"))
+    } else {
+        Cow::Owned(format!("\"{value}\""))
+    }
+}
+
+/// Get the env files to load for the directory, which are either the ones
+/// given with `--file` or the allowed ones
+fn env_files(settings: &EnvySettings, dir: &Path, files: &[PathBuf]) -> Result<Vec<PathBuf>> {
+    Ok(env_files_override(files)?.unwrap_or_else(|| settings.matching_env_files(dir)))
+}
+
+/// Make clear that `--file` bypasses the allow list
+fn warn_unchecked_files(files: &[PathBuf]) {
+    if !files.is_empty() {
+        eprintln!("envy: Note: not checking whether the files given with --file are allowed");
+    }
+}
+
+/// Get the absolute paths of the files given with `--file`, if any
+fn env_files_override(files: &[PathBuf]) -> Result<Option<Vec<PathBuf>>> {
+    if files.is_empty() {
+        return Ok(None);
+    }
+    let files = files
+        .iter()
+        .map(std::path::absolute)
+        .collect::<io::Result<Vec<_>>>()?;
+    Ok(Some(files))
+}
+
+/// Get all env vars for the directory along with the file or pattern they
+/// come from. Variables from env files override the ones from patterns.
+fn collect_env_vars(
+    settings: &EnvySettings,
+    dir: &Path,
+    env_files: &[PathBuf],
+    profile: Option<&str>,
+) -> Result<Vec<(String, String)>> {
+    let mut env_vars = Vec::new();
+    if let Some(path) = settings.matching_pattern_config(dir) {
+        let source = format!("pattern {}", path.pattern);
+        for var in settings.pattern_env_vars(path)? {
+            env_vars.push((var, source.clone()));
+        }
+    }
+    for (env_file, vars) in env_files.iter().zip(get_env_vars_per_file(env_files)?) {
+        let source = env_file.display().to_string();
+        env_vars.extend(vars.into_iter().map(|var| (var, source.clone())));
+    }
+    // A profile is selected explicitly, so it overrides everything else
+    if let Some((name, profile)) = settings.profile(profile)? {
+        let source = format!("profile {name}");
+        for var in settings.profile_env_vars(profile)? {
+            env_vars.push((var, source.clone()));
+        }
+    }
+    Ok(env_vars)
+}
+
+/// Write the commands that set the given env vars in the given shell
+fn format_exports(
+    out: &mut dyn Write,
+    env_vars: &[String],
+    shell: &str,
+    local: bool,
+) -> Result<()> {
+    match shell {
+        "bash" | "zsh" if local => assign(out, env_vars),
+        "bash" | "zsh" => source(out, env_vars),
+        _ if local => Err(anyhow!("--local is not supported for {}", shell)),
+        "fish" => export_fish(out, env_vars),
+        "tcsh" | "csh" => export_tcsh(out, env_vars),
+        "xonsh" => export_xonsh(out, env_vars),
+        "json" => export_json(out, env_vars, None),
+        _ => Err(unsupported_shell(
+            shell,
+            &[&SUPPORTED_SHELLS[..], &["json"]].concat(),
+        )),
+    }
+}
+
+/// Drop variables whose names are not valid shell identifiers,
+/// because a single one of them breaks the evaluation of the whole export.
+/// In strict mode, this is an error instead.
+fn check_env_keys(env_vars: Vec<String>, strict: bool) -> Result<Vec<String>> {
+    let mut valid = Vec::new();
+    for var in env_vars {
+        match split_env_var(&var) {
+            Some((key, _)) if !is_valid_env_key(key) => {
+                if strict {
+                    return Err(anyhow!("Invalid variable name: {key}"));
                 }
-            };
-            for env_file in settings.matching_env_files(&current_dir()?) {
-                let vars = get_env_vars_from_file(&env_file)?;
-                for var in vars {
-                    println!("set -gx {var} (string split ' ' '{var}')");
+                eprintln!("envy: Skipping invalid variable name: {key}");
+            }
+            _ => valid.push(var),
+        }
+    }
+    Ok(valid)
+}
+
+/// Variables that `--no-inherit` keeps, because hardly anything works without them
+const ESSENTIAL_ENV_KEYS: &[&str] = &[
+    "PATH",
+    "HOME",
+    "USER",
+    "LOGNAME",
+    "SHELL",
+    "TERM",
+    "LANG",
+    "LC_*",
+    "TZ",
+    "TMPDIR",
+    "PWD",
+    "OLDPWD",
+    "SHLVL",
+    "DISPLAY",
+    "WAYLAND_DISPLAY",
+    "XDG_*",
+    "SSH_AUTH_SOCK",
+    "ENVY_*",
+];
+
+/// Get the names of the variables in the current environment that are
+/// neither essential nor set by the given env vars, sorted by name
+fn inherited_env_keys(env_vars: &[String]) -> Vec<String> {
+    let mut keys: Vec<String> = env::vars_os()
+        .filter_map(|(key, _)| key.into_string().ok())
+        .filter(|key| is_valid_env_key(key))
+        .filter(|key| {
+            !ESSENTIAL_ENV_KEYS
+                .iter()
+                .any(|pattern| wildcard_match(pattern, key))
+        })
+        .filter(|key| !env_vars.iter().any(|var| env_key(var) == Some(key)))
+        .collect();
+    keys.sort();
+    keys
+}
+
+/// Print the variables that would be added (`+`), changed (`~`) or removed (`-`)
+/// compared to the current environment to stderr
+fn print_env_diff(env_vars: &[String]) {
+    for (key, value) in final_values(env_vars) {
+        match (env::var(key).ok(), value) {
+            (None, Some(value)) => eprintln!("+ {key}={value}"),
+            (Some(old), Some(value)) if old != value => eprintln!("~ {key}: {old} -> {value}"),
+            (Some(_), None) => eprintln!("- {key}"),
+            _ => {}
+        }
+    }
+}
+
+/// Get the value each variable ends up with, which is `None` if it gets unset
+fn final_values(env_vars: &[String]) -> BTreeMap<&str, Option<&str>> {
+    // Later definitions of a variable override earlier ones
+    let mut values = BTreeMap::new();
+    for var in env_vars {
+        if let Some(key) = env_key(var) {
+            values.insert(key, split_env_var(var).map(|(_, value)| value));
+        }
+    }
+    values
+}
+
+/// Drop variables that are already set to their final value in the environment
+fn retain_changed(env_vars: &mut Vec<String>) {
+    let unchanged: BTreeSet<String> = final_values(env_vars)
+        .into_iter()
+        .filter(|(key, value)| env::var(key).ok().as_deref() == *value)
+        .map(|(key, _)| key.to_string())
+        .collect();
+    env_vars.retain(|var| match env_key(var) {
+        Some(key) => !unchanged.contains(key),
+        None => true,
+    });
+}
+
+/// Warn about variables whose names only differ in case,
+/// because they refer to the same variable on macOS and Windows
+fn warn_case_collisions(env_vars: &[String]) {
+    let mut keys: BTreeMap<String, Vec<&str>> = BTreeMap::new();
+    for (key, _) in env_vars.iter().filter_map(|var| split_env_var(var)) {
+        let spellings = keys.entry(key.to_uppercase()).or_default();
+        if !spellings.contains(&key) {
+            spellings.push(key);
+        }
+    }
+    for spellings in keys.values().filter(|spellings| spellings.len() > 1) {
+        eprintln!(
+            "envy: Warning: variable names only differ in case: {}",
+            spellings.join(", ")
+        );
+    }
+}
+
+enum WatchEvent {
+    Changed,
+    Interrupted,
+}
+
+/// Print the exports for the current directory whenever one of the matching
+/// env files or the config changes, until interrupted
+fn watch(shell: Option<String>, options: ExportOptions) -> Result<()> {
+    let config = config_path()?;
+    let dir = current_dir()?;
+    let settings = Settings::load_layered(config.clone(), &dir)?;
+    let shell = resolve_shell(shell, Some(&settings));
+    warn_unchecked_files(&options.files);
+    print!("{}", export_env(&settings, &dir, &shell, &options)?.0);
+    io::stdout().flush()?;
+
+    let (tx, rx) = mpsc::channel();
+    let interrupt = tx.clone();
+    ctrlc::set_handler(move || {
+        let _ = interrupt.send(WatchEvent::Interrupted);
+    })
+    .context("Cannot set interrupt handler")?;
+
+    // The files change along with the config, so the watcher gets them
+    // through a shared list
+    let watched = Arc::new(Mutex::new(Vec::new()));
+    let files = Arc::clone(&watched);
+    let mut watcher = notify::recommended_watcher(move |event: notify::Result<notify::Event>| {
+        if let Ok(event) = event {
+            let files = files.lock().unwrap_or_else(|e| e.into_inner());
+            if !event.kind.is_access() && event.paths.iter().any(|path| files.contains(path)) {
+                let _ = tx.send(WatchEvent::Changed);
+            }
+        }
+    })
+    .context("Cannot create file watcher")?;
+    let mut dirs = BTreeSet::new();
+    let files = watched_files(&settings, &dir, &options, &config)?;
+    watch_files(&mut watcher, &watched, &mut dirs, files)?;
+
+    loop {
+        match rx.recv() {
+            Ok(WatchEvent::Changed) => {}
+            Ok(WatchEvent::Interrupted) | Err(_) => return Ok(()),
+        }
+        // A single save usually triggers a burst of events
+        thread::sleep(Duration::from_millis(100));
+        if rx
+            .try_iter()
+            .any(|event| matches!(event, WatchEvent::Interrupted))
+        {
+            return Ok(());
+        }
+
+        let reloaded = Settings::load_layered(config.clone(), &dir).and_then(|settings| {
+            let (output, _) = export_env(&settings, &dir, &shell, &options)?;
+            Ok((output, watched_files(&settings, &dir, &options, &config)?))
+        });
+        match reloaded {
+            Ok((output, files)) => {
+                print!("{output}");
+                // The config might have added or removed env files
+                if let Err(e) = watch_files(&mut watcher, &watched, &mut dirs, files) {
+                    eprintln!("Error: {e:?}");
                 }
             }
+            Err(e) => eprintln!("Error: {e:?}"),
         }
-        _ => return Err(anyhow!("{} is currently not supported", shell)),
-    };
+        io::stdout().flush()?;
+    }
+}
+
+/// Get the files whose changes affect the exports for the directory
+fn watched_files(
+    settings: &EnvySettings,
+    dir: &Path,
+    options: &ExportOptions,
+    config: &Path,
+) -> Result<Vec<PathBuf>> {
+    let mut files = env_files(settings, dir, &options.files)?;
+    files.extend(settings.pattern_env_files());
+    files.extend(get_included_env_files(&files));
+    files.extend(get_watched_files(&files));
+    files.extend(settings.project_config.clone());
+    files.push(config.to_path_buf());
+    Ok(files)
+}
+
+/// Report changes of the given files from now on, and watch their parent
+/// directories instead of the ones of the previous files
+fn watch_files(
+    watcher: &mut impl Watcher,
+    watched: &Mutex<Vec<PathBuf>>,
+    dirs: &mut BTreeSet<PathBuf>,
+    files: Vec<PathBuf>,
+) -> Result<()> {
+    // Watch the parent directories instead of the files themselves,
+    // because many editors replace the file on save
+    let new_dirs: BTreeSet<PathBuf> = files
+        .iter()
+        .filter_map(|file| file.parent())
+        .map(Path::to_path_buf)
+        .collect();
+    *watched.lock().unwrap_or_else(|e| e.into_inner()) = files;
+    for dir in dirs.difference(&new_dirs) {
+        let _ = watcher.unwatch(dir);
+    }
+    dirs.retain(|dir| new_dirs.contains(dir));
+    for dir in new_dirs {
+        if !dirs.contains(&dir) {
+            watcher
+                .watch(&dir, RecursiveMode::NonRecursive)
+                .with_context(|| format!("Cannot watch {}", dir.display()))?;
+            dirs.insert(dir);
+        }
+    }
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn vars() -> Vec<String> {
+        ["FOO=bar", "SPACED=a b", "QUOTED=\"x y\"", "unset OLD"]
+            .map(String::from)
+            .to_vec()
+    }
+
+    /// Run a formatter on the test variables and get what it wrote
+    fn format(formatter: impl Fn(&mut dyn Write, &[String]) -> Result<()>) -> Vec<u8> {
+        let mut out = Vec::new();
+        formatter(&mut out, &vars()).unwrap();
+        out
+    }
+
+    #[test]
+    fn format_source() {
+        assert_eq!(
+            format(source),
+            b"export FOO=bar\nexport SPACED=\"a b\"\nexport QUOTED=\"x y\"\nunset OLD\n"
+        );
+    }
+
+    #[test]
+    fn format_assign() {
+        assert_eq!(
+            format(assign),
+            b"FOO=bar\nSPACED=\"a b\"\nQUOTED=\"x y\"\nunset OLD\n"
+        );
+    }
+
+    #[test]
+    fn format_fish() {
+        assert_eq!(
+            format(export_fish),
+            b"set -gx FOO bar\nset -gx SPACED \"a b\"\nset -gx QUOTED \"x y\"\nset -e OLD\n"
+        );
+    }
+
+    #[test]
+    fn format_tcsh() {
+        assert_eq!(
+            format(export_tcsh),
+            b"setenv FOO bar;\nsetenv SPACED \"a b\";\nsetenv QUOTED \"x y\";\nunsetenv OLD;\n"
+        );
+    }
+
+    #[test]
+    fn format_xonsh() {
+        assert_eq!(
+            format(export_xonsh),
+            b"$FOO = \"bar\"\n$SPACED = \"a b\"\n$QUOTED = \"x y\"\n${...}.pop(\"OLD\", None)\n"
+        );
+    }
+
+    #[test]
+    fn format_json() {
+        assert_eq!(
+            format(|out, vars| export_json(out, vars, None)),
+            b"{\n  \"FOO\": \"bar\",\n  \"SPACED\": \"a b\",\n  \"QUOTED\": \"x y\"\n}\n"
+        );
+    }
+
+    #[test]
+    fn format_null() {
+        assert_eq!(format(export_null), b"FOO=bar\0SPACED=a b\0QUOTED=x y\0");
+    }
+
+    #[test]
+    fn format_base64() {
+        assert_eq!(
+            format(|out, vars| export_base64(out, vars, false)),
+            b"export FOO=\"$(printf %s YmFy | base64 -d)\"\n\
+              export SPACED=\"$(printf %s YSBi | base64 -d)\"\n\
+              export QUOTED=\"$(printf %s eCB5 | base64 -d)\"\n\
+              unset OLD\n"
+        );
+        assert!(format(|out, vars| export_base64(out, vars, true)).starts_with(b"FOO="));
+    }
+
+    #[test]
+    fn skip_invalid_variable_names() {
+        let vars = ["1FOO=a", "FOO-BAR=b", "_FOO1=c"]
+            .map(String::from)
+            .to_vec();
+        assert_eq!(check_env_keys(vars.clone(), false).unwrap(), ["_FOO1=c"]);
+        let error = check_env_keys(vars, true).unwrap_err().to_string();
+        assert_eq!(error, "Invalid variable name: 1FOO");
+    }
+}
@@ -1,52 +1,275 @@
-use std::path::PathBuf;
-use structopt::StructOpt;
+use crate::env_file::EnvFormat;
+use regex::Regex;
+use std::{
+    env,
+    io::{self, IsTerminal},
+    path::PathBuf,
+};
+use structopt::{clap::Shell, StructOpt};
 
 #[derive(StructOpt)]
 #[structopt(name = "envy", about = "context-based environment variables")]
 pub struct Envy {
+    /// When to color the output (`NO_COLOR` turns off `auto`)
+    #[structopt(
+        long,
+        global = true,
+        default_value = "auto",
+        possible_values = &["auto", "always", "never"]
+    )]
+    pub color: String,
+    /// Don't color the output, same as `--color never`
+    #[structopt(long, global = true)]
+    pub no_color: bool,
+    /// Leave out informational messages and headers, e.g. in `envy show`
+    #[structopt(short, long, global = true)]
+    pub quiet: bool,
     #[structopt(subcommand)]
     pub cmd: Command,
 }
 
+impl Envy {
+    pub fn use_color(&self) -> bool {
+        match self.color.as_str() {
+            _ if self.no_color => false,
+            "always" => true,
+            "never" => false,
+            _ => {
+                io::stdout().is_terminal()
+                    && env::var_os("NO_COLOR").is_none_or(|value| value.is_empty())
+            }
+        }
+    }
+}
+
 #[derive(StructOpt)]
 pub enum Command {
     /// Export environment variables based on the current directory
     #[structopt(name = "export")]
-    Export { shell: String },
+    Export {
+        /// Defaults to `default_shell` from the config or to the detected shell
+        shell: Option<String>,
+        #[structopt(flatten)]
+        options: ExportOptions,
+        /// Write the commands to this file or named pipe instead of stdout
+        #[structopt(long, parse(from_os_str))]
+        output: Option<PathBuf>,
+        /// Export the variables for this directory instead of the current one
+        #[structopt(long, parse(from_os_str))]
+        dir: Option<PathBuf>,
+        /// Print the shell detected from `$SHELL` or the parent process instead of exporting
+        #[structopt(long)]
+        shell_detect: bool,
+        /// Only check that the variables resolve cleanly and print a summary without values
+        #[structopt(long)]
+        check: bool,
+    },
+    /// Export environment variables again whenever a matching env file changes
+    #[structopt(name = "watch")]
+    Watch {
+        /// Defaults to `default_shell` from the config or to the detected shell
+        shell: Option<String>,
+        #[structopt(flatten)]
+        options: ExportOptions,
+    },
     /// Print the hook to activate envy for your shell
     #[structopt(name = "hook")]
-    Hook { shell: String },
+    Hook {
+        /// Defaults to `default_shell` from the config or to the detected shell
+        shell: Option<String>,
+        /// Print the path of the envy binary that the hook would call instead of the hook
+        #[structopt(long)]
+        print_path: bool,
+    },
     /// Edit the envy config file
     #[structopt(name = "edit")]
     Edit {},
     /// Show envy config for current directory
     #[structopt(name = "show")]
-    Show {},
-    /// Find a single environment variable and print its value
+    Show {
+        /// Hide the values of variables that look like secrets
+        #[structopt(long)]
+        mask: bool,
+        /// Show these env files instead of the allowed ones (without checking the allow list)
+        #[structopt(long = "file", parse(from_os_str), number_of_values = 1)]
+        files: Vec<PathBuf>,
+    },
+    /// Find environment variables and print their values
     #[structopt(name = "find")]
     Find {
+        #[structopt(name = "VARIABLE", required = true)]
+        variables: Vec<String>,
+    },
+    /// Print the env file or pattern that defines a variable for the current directory
+    #[structopt(name = "which")]
+    Which {
         #[structopt(name = "VARIABLE")]
         variable: String,
     },
+    /// Inspect the envy config file
+    #[structopt(name = "config")]
+    Config(ConfigCommand),
+    /// Print a completion script for the given shell
+    #[structopt(name = "completions")]
+    Completions {
+        #[structopt(possible_values = &Shell::variants(), case_insensitive = true)]
+        shell: Shell,
+    },
     /// Print path to envy config file
     #[structopt(name = "path")]
     Path {},
+    /// Create the envy config file with a commented example
+    #[structopt(name = "init")]
+    Init {
+        /// Overwrite an existing config file
+        #[structopt(long)]
+        force: bool,
+    },
     /// Load environment variables from a given `.env` file (for the current session only)
     #[structopt(name = "load")]
     Load {
         #[structopt(parse(from_os_str), default_value = ".env")]
         env_file: PathBuf,
+        /// Load the output of this shell command instead, e.g. `op read ...`
+        #[structopt(long, conflicts_with = "env-file")]
+        command: Option<String>,
+        /// Format of the variables: `dotenv` or `yaml` (`KEY: value`).
+        /// Defaults to `yaml` for files ending in `.yaml` or `.yml`
+        #[structopt(long)]
+        format: Option<EnvFormat>,
+        /// Shell to print the commands for
+        #[structopt(long, default_value = "bash")]
+        into: String,
     },
     /// Grants envy to load the given `.env` file
     #[structopt(name = "allow")]
     Allow {
         #[structopt(parse(from_os_str), default_value = ".env")]
         env_file: PathBuf,
+        /// Record why the file is allowed, e.g. the project it belongs to
+        #[structopt(long)]
+        note: Option<String>,
+        /// Allow all env files matching this glob instead, e.g. `~/work/*/.env`
+        #[structopt(long, conflicts_with = "env-file")]
+        glob: Option<String>,
+        /// Show the values of the variables in addition to their names
+        #[structopt(long)]
+        show_values: bool,
+        /// Don't ask for confirmation
+        #[structopt(short, long)]
+        yes: bool,
+        /// Print the changes to the allowed files without saving them
+        #[structopt(long)]
+        dry_run: bool,
+    },
+    /// Renew the trust in an already allowed `.env` file without asking again
+    #[structopt(name = "refresh")]
+    Refresh {
+        #[structopt(parse(from_os_str), default_value = ".env")]
+        env_file: PathBuf,
     },
     /// Revokes the authorization of a given `.env` file
     #[structopt(name = "deny")]
     Deny {
         #[structopt(parse(from_os_str), default_value = ".env")]
         env_file: PathBuf,
+        /// Revoke all allowed env files
+        #[structopt(long)]
+        all: bool,
+        /// Don't ask for confirmation
+        #[structopt(short, long)]
+        yes: bool,
+        /// Print the changes to the allowed files without saving them
+        #[structopt(long)]
+        dry_run: bool,
+    },
+}
+
+#[derive(StructOpt)]
+pub enum ConfigCommand {
+    /// Check the config file for problems
+    #[structopt(name = "validate")]
+    Validate {},
+    /// Print how envy interprets the config file
+    #[structopt(name = "explain")]
+    Explain {},
+    /// Print a setting, or all of them if no key is given
+    #[structopt(name = "get")]
+    Get { key: Option<String> },
+    /// Change a setting, e.g. `envy config set source_up true`
+    ///
+    /// The value is parsed as TOML and falls back to a plain string.
+    #[structopt(name = "set")]
+    Set { key: String, value: String },
+    /// Add a pattern with the given variables to `paths`
+    #[structopt(name = "add-path")]
+    AddPath {
+        pattern: String,
+        /// Variables to set for the pattern, like `KEY=value`
+        env: Vec<String>,
     },
+    /// Remove the pattern from `paths`, given as is or by its position in `envy config explain`
+    #[structopt(name = "remove-path")]
+    RemovePath { pattern: String },
+}
+
+// Options shared by the commands that export variables.
+// (A doc comment here would replace the help of the flattening subcommands.)
+#[derive(StructOpt)]
+pub struct ExportOptions {
+    /// Only export the variables with these names (comma-separated)
+    #[structopt(long, use_delimiter = true)]
+    pub only: Vec<String>,
+    /// Don't export the variables with these names (comma-separated)
+    #[structopt(long, use_delimiter = true)]
+    pub except: Vec<String>,
+    /// Only export the variables with names matching this regular expression, e.g. `^APP_`
+    #[structopt(long, parse(try_from_str = Regex::new))]
+    pub env_filter: Option<Regex>,
+    /// Prepend this prefix to the names of all variables, unless they already start with it
+    #[structopt(long)]
+    pub prefix: Option<String>,
+    /// Skip variables that are already set to the same value in the environment
+    #[structopt(long)]
+    pub print_changed_only: bool,
+    /// Load these env files instead of the allowed ones (without checking the allow list)
+    #[structopt(long = "file", parse(from_os_str), number_of_values = 1)]
+    pub files: Vec<PathBuf>,
+    /// Include the env file or pattern each variable comes from (json only)
+    #[structopt(long)]
+    pub with_source: bool,
+    /// Print plain `KEY=value` assignments instead of `export` statements (bash and zsh only)
+    #[structopt(long)]
+    pub local: bool,
+    /// Pass values base64-encoded and decode them when the output is evaluated (bash and zsh only)
+    #[structopt(long)]
+    pub eval_safe: bool,
+    /// Print `KEY=value` records terminated by NUL bytes instead of shell commands, e.g. for `xargs -0`
+    #[structopt(long)]
+    pub null: bool,
+    /// Add the variables of this profile from the config instead of the default one
+    #[structopt(long)]
+    pub profile: Option<String>,
+    /// Sort the variables by name instead of keeping the order of the env files
+    #[structopt(long)]
+    pub sort: bool,
+    /// Unset all other variables of the current environment, except essential ones like `PATH`
+    #[structopt(long)]
+    pub no_inherit: bool,
+    /// Print how the variables would change the current environment to stderr instead
+    #[structopt(long)]
+    pub diff: bool,
+    /// Print this file with its `{{KEY}}` placeholders replaced by the values of the variables
+    #[structopt(long, parse(from_os_str))]
+    pub template: Option<PathBuf>,
+    /// Replace placeholders of undefined variables with nothing instead of failing (with --template)
+    #[structopt(long, requires = "template")]
+    pub allow_undefined: bool,
+}
+
+impl ExportOptions {
+    pub fn allows(&self, key: &str) -> bool {
+        (self.only.is_empty() || self.only.iter().any(|k| k == key))
+            && !self.except.iter().any(|k| k == key)
+    }
 }
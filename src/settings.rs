@@ -1,84 +1,1140 @@
-use anyhow::{Context, Result};
+use crate::env_file::{get_env_vars_from_file, is_env_var};
+use crate::shell::SUPPORTED_SHELLS;
+use anyhow::{bail, Context, Result};
+use directories::BaseDirs;
 use regex::Regex;
 use serde::{Deserialize, Serialize};
 use std::{
+    collections::BTreeMap,
     fs,
-    path::{Path, PathBuf},
+    path::{Component, Path, PathBuf},
+    time::{SystemTime, UNIX_EPOCH},
 };
+use toml_edit::{Array, ArrayOfTables, Decor, DocumentMut, Item, RawString, Table, Value};
+
+// Name of the config file that a project can ship in its repository
+pub const PROJECT_CONFIG_NAME: &str = ".envy.toml";
+
+// Names of variables whose values are hidden by `envy show --mask`
+const SECRET_PATTERNS: &[&str] = &[
+    "*_KEY",
+    "*_TOKEN",
+    "*_SECRET",
+    "*PASSWORD*",
+    "*_CREDENTIALS",
+];
+
+// Config locations of older versions, relative to the home directory.
+// Before `XDG_CONFIG_HOME` was checked explicitly, envy could end up using
+// `~/.config` on Linux even if `XDG_CONFIG_HOME` pointed elsewhere.
+const LEGACY_CONFIG_PATHS: &[&str] = &[".config/envy/Config.toml"];
+
+// Commented example config written by `envy init`
+pub static CONFIG_TEMPLATE: &str = r#"# envy config file
+
+# Env files that envy is allowed to load.
+# They are loaded in their directory and all of its subdirectories.
+# Use `envy allow` and `envy deny` to manage this list.
+# `envy allow --note` records why a file was allowed.
+# envs = [
+#   "/home/user/project/.env",
+#   { path = "/home/user/work/.env", note = "work laptop" },
+#   { glob = "~/projects/*/.env" },
+# ]
+
+# Load `.env` files from all parent directories up to the git root.
+# source_up = true
+
+# Only export variables with these names.
+# allowlist = ["DATABASE_URL", "APP_*"]
+
+# Variables that never get exported, even if they are set in a matching file.
+# `*` and `?` can be used as wildcards.
+# blocklist = ["AWS_SECRET_ACCESS_KEY", "*_TOKEN"]
+
+# Variables with names that are not valid shell identifiers (like `1FOO`) are
+# skipped with a warning. Set this to fail instead.
+# strict_keys = true
+
+# Hide the values of secrets in `envy show`, as if `--mask` was passed.
+# Besides the built-in patterns like `*_TOKEN`, more names can be added.
+# mask = true
+# secret_patterns = ["DATABASE_URL"]
+
+# Shell to export for if `envy export` is called without one.
+# Defaults to the shell in `$SHELL`.
+# default_shell = "fish"
+
+# `KEY+=value` in an env file appends to the value of `KEY` from earlier files
+# or the current environment, separated by a colon like in `PATH`.
+# append_separator = ":"
+
+# Directories that a pattern puts in front of `PATH`, like direnv's `PATH_add`
+# [[paths]]
+# pattern = ".*project3.*"
+# path_add = ["bin", "~/tools/project3"]
+
+# Variables that other values can refer to with `${KEY}`, without exporting
+# them. Relative paths are resolved against the directory of this file.
+# defaults_file = "defaults.env"
+
+# Named sets of variables that `envy export --profile staging` adds on top of
+# the matching ones, given like the ones of a pattern below.
+# [profiles.staging]
+# env = { API_URL = "https://staging.example.com" }
+# env_file = "staging.env"
+# Profile to use if `--profile` isn't given
+# default_profile = "staging"
+
+# Stop loading allowed env files this many days after they were allowed.
+# Run `envy allow` again to renew the trust in a file.
+# trust_ttl_days = 30
+
+# Environment variables for all directories matching a regular expression.
+# The first matching pattern wins.
+# [[paths]]
+# pattern = ".*project1.*"
+# env = [
+#   "CONSUL_HTTP_ADDR=http://consul:8500",
+#   "GITHUB_TOKEN=123",
+# ]
+# or as a table: env = { CONSUL_HTTP_ADDR = "http://consul:8500" }
+# env_file = "/home/user/shared.env"
+# Relative paths are resolved against the directory of this file
+# env_file = "shared.env"
+"#;
 
 #[derive(Debug, Serialize, Deserialize)]
 pub struct EnvySettings {
-    pub envs: Option<Vec<PathBuf>>,
+    pub envs: Option<Vec<AllowedEnv>>,
+    // Load `.env` files from all parent directories up to the git root,
+    // similar to direnv's `source_up`
+    pub source_up: Option<bool>,
+    // If set, only variables with these names get exported.
+    // Supports the same wildcards as `blocklist`, which is applied afterwards.
+    pub allowlist: Option<Vec<String>>,
+    // Names of variables that never get exported.
+    // `*` and `?` can be used as wildcards, e.g. `*_TOKEN`.
+    pub blocklist: Option<Vec<String>>,
+    // Fail instead of skipping variables with invalid names
+    pub strict_keys: Option<bool>,
+    // Always hide secret values in `envy show`
+    pub mask: Option<bool>,
+    // Additional names of variables that contain secrets,
+    // supporting the same wildcards as `blocklist`
+    pub secret_patterns: Option<Vec<String>>,
     pub paths: Option<Vec<PathConfig>>,
+    // Shell to use if none is given on the command line
+    pub default_shell: Option<String>,
+    // Named sets of variables that `envy export --profile` adds on top
+    pub profiles: Option<BTreeMap<String, ProfileConfig>>,
+    // Profile to use if none is given on the command line
+    pub default_profile: Option<String>,
+    // Env file with variables that `${KEY}` in other values refers to,
+    // without exporting them
+    pub defaults_file: Option<PathBuf>,
+    // Separator between the values joined by `KEY+=value`, `:` by default
+    pub append_separator: Option<String>,
+    // Stop loading allowed env files this many days after they were allowed
+    pub trust_ttl_days: Option<u64>,
+    // Directory of the config file, which relative env files are resolved against
+    #[serde(skip)]
+    pub config_dir: PathBuf,
+    // The `.envy.toml` of the current project that got merged into the settings
+    #[serde(skip)]
+    pub project_config: Option<PathBuf>,
 }
 
 impl EnvySettings {
-    // Add a path to an env file to the list of allowed files
-    pub fn add_env(&mut self, path: PathBuf) -> &mut Self {
-        // Add directory to settings
-        match self.envs.as_mut() {
-            Some(envs) => {
-                if !envs.contains(&path) {
-                    envs.push(path);
-                }
+    // Add a path to an env file to the list of allowed files.
+    // Allowing a file again renews its trust and keeps its note,
+    // unless a new one is given.
+    pub fn add_env(&mut self, path: PathBuf, note: Option<String>) -> &mut Self {
+        self.insert_env(AllowedEnv::Entry {
+            path,
+            note,
+            allowed_at: Some(unix_time()),
+        })
+    }
+
+    // Add a glob like `~/work/*/.env` to the list of allowed files,
+    // which allows all env files matching it
+    pub fn add_env_glob(&mut self, glob: String, note: Option<String>) -> &mut Self {
+        self.insert_env(AllowedEnv::Glob {
+            glob,
+            note,
+            allowed_at: Some(unix_time()),
+        })
+    }
+
+    fn insert_env(&mut self, mut env: AllowedEnv) -> &mut Self {
+        let envs = self.envs.get_or_insert_with(Vec::new);
+        let existing = envs.iter().position(|e| e.path() == env.path());
+        if let (Some(i), AllowedEnv::Entry { note, .. } | AllowedEnv::Glob { note, .. }) =
+            (existing, &mut env)
+        {
+            if note.is_none() {
+                *note = envs[i].note().map(str::to_string);
             }
-            None => self.envs = Some(vec![path]),
-        };
+        }
+        match existing {
+            Some(i) => envs[i] = env,
+            None => envs.push(env),
+        }
         self
     }
 
     // Remove a path to an env file from the list of allowed files
     pub fn remove_env(&mut self, path: PathBuf) -> &mut Self {
         if let Some(envs) = self.envs.as_mut() {
-            envs.retain(|p| p != &path);
+            envs.retain(|env| env.path() != path);
         };
         self
     }
 
-    pub fn matching_patterns(&self, dir: &Path) -> Option<Vec<String>> {
+    // Get the paths of all allowed env files
+    pub fn env_paths(&self) -> Vec<PathBuf> {
+        self.envs
+            .iter()
+            .flatten()
+            .map(|env| env.path().to_path_buf())
+            .collect()
+    }
+
+    // Add a pattern with the given env vars to the end of `paths`.
+    // A pattern that is already there is rejected, since it would never match.
+    pub fn add_pattern(&mut self, pattern: &str, env: Vec<String>) -> Result<&mut Self> {
+        let pattern = Regex::new(pattern).context("Invalid pattern")?;
+        if self
+            .paths
+            .iter()
+            .flatten()
+            .any(|path| path.pattern.as_str() == pattern.as_str())
+        {
+            bail!("Pattern already exists in config: {pattern}");
+        }
+        self.paths.get_or_insert_with(Vec::new).push(PathConfig {
+            pattern,
+            env,
+            env_file: None,
+            path_add: None,
+        });
+        Ok(self)
+    }
+
+    // Remove all entries of `paths` with the given pattern, or the entry at
+    // the given position (starting at 1, as listed by `envy config explain`).
+    // Returns whether any entry was removed.
+    pub fn remove_pattern(&mut self, pattern: &str) -> bool {
+        let Some(paths) = self.paths.as_mut() else {
+            return false;
+        };
+        let len = paths.len();
+        paths.retain(|path| path.pattern.as_str() != pattern);
+        // A pattern can look like a number, so only fall back to the position
+        if paths.len() == len {
+            if let Some(index) = pattern
+                .parse::<usize>()
+                .ok()
+                .filter(|i| (1..=len).contains(i))
+            {
+                paths.remove(index - 1);
+            }
+        }
+        let removed = paths.len() != len;
+        if paths.is_empty() {
+            self.paths = None;
+        }
+        removed
+    }
+
+    // Get the first pattern that matches the directory
+    pub fn matching_pattern_config(&self, dir: &Path) -> Option<&PathConfig> {
         let path_str = dir.to_string_lossy();
-        for path in self.paths.as_ref()? {
-            if path.pattern.is_match(&path_str) {
-                return Some(path.env.clone());
+        self.paths
+            .iter()
+            .flatten()
+            .find(|path| path.pattern.is_match(&path_str))
+    }
+
+    // Get the env vars of a pattern, including the ones from its env file.
+    // Inline env vars take precedence over the ones from the file.
+    pub fn pattern_env_vars(&self, path: &PathConfig) -> Result<Vec<String>> {
+        self.config_env_vars(&path.env, path.env_file.as_deref())
+    }
+
+    // Get the given profile, or the default one if none is given
+    pub fn profile(&self, name: Option<&str>) -> Result<Option<(&str, &ProfileConfig)>> {
+        let Some(name) = name.or(self.default_profile.as_deref()) else {
+            return Ok(None);
+        };
+        let profiles = self.profiles.iter().flatten();
+        match profiles
+            .clone()
+            .find(|(profile, _)| profile.as_str() == name)
+        {
+            Some((name, profile)) => Ok(Some((name, profile))),
+            None => {
+                let names: Vec<&str> = profiles.map(|(name, _)| name.as_str()).collect();
+                bail!("Unknown profile: {name} (available: {})", names.join(", "))
+            }
+        }
+    }
+
+    // Get the env vars of a profile like the ones of a pattern
+    pub fn profile_env_vars(&self, profile: &ProfileConfig) -> Result<Vec<String>> {
+        self.config_env_vars(&profile.env, profile.env_file.as_deref())
+    }
+
+    fn config_env_vars(&self, env: &[String], env_file: Option<&Path>) -> Result<Vec<String>> {
+        let mut vars = match env_file {
+            Some(env_file) => get_env_vars_from_file(&self.config_dir.join(env_file))?,
+            None => Vec::new(),
+        };
+        // Allow documenting the inline env vars with comments
+        vars.extend(env.iter().filter(|var| is_env_var(var)).cloned());
+        Ok(vars)
+    }
+
+    // Check if the variable with the given name may be exported
+    pub fn is_exported(&self, key: &str) -> bool {
+        if let Some(allowlist) = &self.allowlist {
+            if !allowlist.iter().any(|pattern| wildcard_match(pattern, key)) {
+                return false;
+            }
+        }
+        !self
+            .blocklist
+            .iter()
+            .flatten()
+            .any(|pattern| wildcard_match(pattern, key))
+    }
+
+    // Check if the variable with the given name looks like it contains a secret
+    pub fn is_secret(&self, key: &str) -> bool {
+        let key = key.to_uppercase();
+        SECRET_PATTERNS
+            .iter()
+            .copied()
+            .chain(self.secret_patterns.iter().flatten().map(String::as_str))
+            .any(|pattern| wildcard_match(&pattern.to_uppercase(), &key))
+    }
+
+    // Get the env files referenced by any pattern, profile or `defaults_file`
+    pub fn pattern_env_files(&self) -> Vec<PathBuf> {
+        let profiles = self.profiles.iter().flat_map(BTreeMap::values);
+        self.paths
+            .iter()
+            .flatten()
+            .filter_map(|path| path.env_file.as_ref())
+            .chain(profiles.filter_map(|profile| profile.env_file.as_ref()))
+            .chain(&self.defaults_file)
+            .map(|env_file| self.config_dir.join(env_file))
+            .collect()
+    }
+
+    // Get the paths that decide which env files match dir without being one
+    // of them: the `.env` files `source_up` would pick up once they exist,
+    // and the directories whose listings globs are matched against
+    pub fn env_file_candidates(&self, dir: &Path) -> Vec<PathBuf> {
+        let dir = dir.canonicalize().unwrap_or_else(|_| dir.to_path_buf());
+        let mut candidates = if self.source_up.unwrap_or(false) {
+            source_up_candidates(&dir)
+        } else {
+            Vec::new()
+        };
+        for env in self.envs.iter().flatten() {
+            if let AllowedEnv::Glob { glob, .. } = env {
+                let glob_dirs = glob_dirs(&expand_home(glob), &dir);
+                candidates.extend(glob_dirs.into_iter().map(Path::to_path_buf));
             }
         }
-        None
+        candidates
+    }
+
+    // Get the variables of `defaults_file`, if any
+    pub fn defaults(&self) -> Result<Option<Vec<String>>> {
+        self.defaults_file
+            .as_ref()
+            .map(|file| get_env_vars_from_file(&self.config_dir.join(file)))
+            .transpose()
     }
 
-    // get all env files in dir and parent directory
+    // Get the paths of the allowed env files whose trust hasn't expired.
+    // Files allowed before `allowed_at` was recorded count as expired,
+    // since there is no telling how long ago that was.
+    fn trusted_envs(&self) -> Vec<&AllowedEnv> {
+        let envs = self.envs.iter().flatten();
+        let Some(ttl_days) = self.trust_ttl_days else {
+            return envs.collect();
+        };
+        let now = unix_time();
+        envs.filter(|env| match env.allowed_at() {
+            Some(allowed_at) if now.saturating_sub(allowed_at) <= ttl_days * 24 * 60 * 60 => true,
+            Some(_) => {
+                eprintln!(
+                    "envy: Warning: trust in {} expired after {ttl_days} days, run `envy allow` to allow it again",
+                    env.path().display()
+                );
+                false
+            }
+            None => {
+                eprintln!(
+                    "envy: Warning: {} was allowed before envy recorded when, run `envy allow` to allow it again",
+                    env.path().display()
+                );
+                false
+            }
+        })
+        .collect()
+    }
+
+    // Get all env files in dir and parent directory.
+    // Files in deeper directories come later, so that their variables
+    // override the ones of their parents. Files in the same directory keep
+    // the order of `envs`.
     pub fn matching_env_files(&self, dir: &Path) -> Vec<PathBuf> {
-        self.envs.iter().flatten().filter(|env|
+        // Allowed env files are stored canonicalized, so resolve symlinks
+        // (like `/var` -> `/private/var` on macOS) before comparing
+        let dir = dir.canonicalize().unwrap_or_else(|_| dir.to_path_buf());
+        let dir = dir.as_path();
+        let mut env_files = if self.source_up.unwrap_or(false) {
+            source_up_env_files(dir)
+        } else {
+            Vec::new()
+        };
+        let allowed = self.trusted_envs().into_iter().flat_map(|env| match env {
+            AllowedEnv::Glob { glob, .. } => glob_env_files(glob, dir),
             // check if env file is in dir
-            if let Some(env_dir) = env.parent() {
-                dir.starts_with(env_dir)
-            } else {
-                false
+            // Allowed project configs are merged into the settings instead
+            _ if env.path().ends_with(PROJECT_CONFIG_NAME) => Vec::new(),
+            _ => match env.path().parent() {
+                Some(env_dir) if dir.starts_with(env_dir) => vec![env.path().to_path_buf()],
+                _ => Vec::new(),
+            },
+        });
+        for env in allowed {
+            if !env_files.contains(&env) {
+                env_files.push(env);
             }
-        ).cloned().collect()
+        }
+        // Env files above the closest `.envignore` don't apply to its subtree
+        if let Some(root) = ignore_root(dir) {
+            env_files.retain(|env| {
+                env.parent()
+                    .is_some_and(|env_dir| env_dir.starts_with(root))
+            });
+        }
+        env_files.sort_by_key(|env| env.components().count());
+        env_files
     }
 }
 
+// get all `.env` files from the git root down to dir,
+// so that files closer to dir take precedence
+fn source_up_env_files(dir: &Path) -> Vec<PathBuf> {
+    let mut env_files: Vec<PathBuf> = source_up_candidates(dir)
+        .into_iter()
+        .filter(|env| env.is_file())
+        .collect();
+    env_files.reverse();
+    env_files
+}
+
+// Get the paths of the `.env` files from dir up to the git root,
+// whether they exist or not
+fn source_up_candidates(dir: &Path) -> Vec<PathBuf> {
+    let Some(root) = dir.ancestors().find(|d| d.join(".git").exists()) else {
+        return Vec::new();
+    };
+    dir.ancestors()
+        .filter(|d| d.starts_with(root))
+        .map(|d| d.join(".env"))
+        .collect()
+}
+
+// Get the closest directory containing an `.envignore` file,
+// which stops env files from parent directories from being inherited
+pub fn ignore_root(dir: &Path) -> Option<&Path> {
+    dir.ancestors().find(|d| d.join(".envignore").exists())
+}
+
+// Match a name against a pattern where `*` matches any number of characters
+// and `?` matches exactly one character
+pub fn wildcard_match(pattern: &str, name: &str) -> bool {
+    let pattern: Vec<char> = pattern.chars().collect();
+    let name: Vec<char> = name.chars().collect();
+    let (mut p, mut n) = (0, 0);
+    // Position of the last `*` and the part of the name it matched up to
+    let mut star = None;
+    while n < name.len() {
+        if p < pattern.len() && (pattern[p] == '?' || pattern[p] == name[n]) {
+            p += 1;
+            n += 1;
+        } else if p < pattern.len() && pattern[p] == '*' {
+            star = Some((p, n));
+            p += 1;
+        } else if let Some((star_p, star_n)) = star {
+            // Let the last `*` match one more character and try again
+            p = star_p + 1;
+            n = star_n + 1;
+            star = Some((star_p, star_n + 1));
+        } else {
+            return false;
+        }
+    }
+    pattern[p..].iter().all(|&c| c == '*')
+}
+
+// An allowed env file, either as a plain path or as a table with a note
+// on why it was allowed and when (as a Unix timestamp), e.g.
+// `{ path = "/work/.env", note = "work laptop", allowed_at = 1700000000 }`.
+// Instead of a path, a table can contain a glob like `~/work/*/.env`
+// that allows all matching files.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(untagged)]
+pub enum AllowedEnv {
+    Path(PathBuf),
+    Entry {
+        path: PathBuf,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        note: Option<String>,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        allowed_at: Option<u64>,
+    },
+    Glob {
+        glob: String,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        note: Option<String>,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        allowed_at: Option<u64>,
+    },
+}
+
+impl AllowedEnv {
+    // Get the path of the file, or the glob as it was given
+    pub fn path(&self) -> &Path {
+        match self {
+            AllowedEnv::Path(path) | AllowedEnv::Entry { path, .. } => path,
+            AllowedEnv::Glob { glob, .. } => Path::new(glob),
+        }
+    }
+
+    pub fn note(&self) -> Option<&str> {
+        match self {
+            AllowedEnv::Path(_) => None,
+            AllowedEnv::Entry { note, .. } | AllowedEnv::Glob { note, .. } => note.as_deref(),
+        }
+    }
+
+    pub fn allowed_at(&self) -> Option<u64> {
+        match self {
+            AllowedEnv::Path(_) => None,
+            AllowedEnv::Entry { allowed_at, .. } | AllowedEnv::Glob { allowed_at, .. } => {
+                *allowed_at
+            }
+        }
+    }
+}
+
+// Replace a leading `~` of a glob with the home directory
+pub fn expand_home(glob: &str) -> PathBuf {
+    match (glob.strip_prefix("~/"), BaseDirs::new()) {
+        (Some(rest), Some(base_dirs)) => base_dirs.home_dir().join(rest),
+        _ => PathBuf::from(glob),
+    }
+}
+
+// Get the files matching the glob in the directory and its parents,
+// e.g. `~/work/*/.env` matches `~/work/project/.env` in `~/work/project/src`.
+// `*` and `?` only match within a single path component.
+fn glob_env_files(glob: &str, dir: &Path) -> Vec<PathBuf> {
+    let glob = expand_home(glob);
+    let Some(name) = glob.file_name() else {
+        return Vec::new();
+    };
+    let name = name.to_string_lossy();
+    let mut files = Vec::new();
+    for glob_dir in glob_dirs(&glob, dir) {
+        let Ok(entries) = fs::read_dir(glob_dir) else {
+            continue;
+        };
+        let mut matching: Vec<PathBuf> = entries
+            .flatten()
+            .filter(|entry| wildcard_match(&name, &entry.file_name().to_string_lossy()))
+            .map(|entry| entry.path())
+            .filter(|path| path.is_file())
+            .collect();
+        matching.sort();
+        files.extend(matching);
+    }
+    files
+}
+
+// Get the directory and its parents that match the directory part of the glob
+fn glob_dirs<'a>(glob: &Path, dir: &'a Path) -> Vec<&'a Path> {
+    let Some(glob_dir) = glob.parent() else {
+        return Vec::new();
+    };
+    let components_match = |path: &Path| {
+        path.components().count() == glob_dir.components().count()
+            && glob_dir
+                .components()
+                .zip(path.components())
+                .all(|(pattern, component)| {
+                    wildcard_match(
+                        &pattern.as_os_str().to_string_lossy(),
+                        &component.as_os_str().to_string_lossy(),
+                    )
+                })
+    };
+    dir.ancestors()
+        .filter(|ancestor| components_match(ancestor))
+        .collect()
+}
+
+// Current time in seconds since the epoch
+fn unix_time() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |time| time.as_secs())
+}
+
 #[derive(Debug, Serialize, Deserialize)]
 pub struct PathConfig {
     #[serde(with = "serde_regex")]
     pub pattern: Regex,
+    // Either a list like `["FOO=bar"]` or a table like `{ FOO = "bar" }`
+    #[serde(default, deserialize_with = "deserialize_env")]
     pub env: Vec<String>,
+    // Env file to load in addition to `env` when the pattern matches.
+    // Relative paths are resolved against the directory of the config file.
+    pub env_file: Option<PathBuf>,
+    // Directories to put in front of `PATH` when the pattern matches.
+    // Relative paths are resolved against the current directory.
+    pub path_add: Option<Vec<PathBuf>>,
+}
+
+// A named set of variables, given like the ones of a pattern
+#[derive(Debug, Serialize, Deserialize)]
+pub struct ProfileConfig {
+    #[serde(default, deserialize_with = "deserialize_env")]
+    pub env: Vec<String>,
+    pub env_file: Option<PathBuf>,
+}
+
+// Both forms of `env` that a pattern accepts
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum EnvList {
+    List(Vec<String>),
+    Table(serde_json::Map<String, serde_json::Value>),
+}
+
+// Normalize the table form of `env` into `KEY=value` entries
+fn deserialize_env<'de, D>(deserializer: D) -> Result<Vec<String>, D::Error>
+where
+    D: serde::Deserializer<'de>,
+{
+    Ok(match EnvList::deserialize(deserializer)? {
+        EnvList::List(env) => env,
+        EnvList::Table(env) => env
+            .into_iter()
+            .map(|(key, value)| match value {
+                serde_json::Value::String(value) => format!("{key}={value}"),
+                value => format!("{key}={value}"),
+            })
+            .collect(),
+    })
+}
+
+// A `PathConfig` with the pattern not yet compiled
+#[derive(Deserialize)]
+struct RawPathConfig {
+    pattern: String,
 }
 
 pub(crate) struct Settings {}
 
 impl Settings {
     pub fn load(config_path: PathBuf) -> Result<EnvySettings> {
+        Self::migrate_legacy_config(&config_path)?;
+        Self::parse(config_path)
+    }
+
+    // Load the config and merge the `.envy.toml` of the closest directory
+    // above `dir` into it, as long as that file is allowed like an env file.
+    // Its patterns are appended, so the ones of the user config still win,
+    // and its allowed env files are added to the ones of the user config.
+    // Only use this for reading, since saving would copy the merged settings.
+    pub fn load_layered(config_path: PathBuf, dir: &Path) -> Result<EnvySettings> {
+        let mut settings = Self::load(config_path)?;
+        let Some(project_config) = find_project_config(dir) else {
+            return Ok(settings);
+        };
+        if !settings.env_paths().contains(&project_config) {
+            eprintln!(
+                "envy: Ignoring {0}, run `envy allow {0}` to load it",
+                project_config.display()
+            );
+            return Ok(settings);
+        }
+        let project = Self::parse(project_config.clone())
+            .with_context(|| format!("Cannot load {}", project_config.display()))?;
+        let project_dir = project.config_dir;
+        for mut path in project.paths.into_iter().flatten() {
+            // Relative env files are resolved against the user config otherwise
+            path.env_file = path
+                .env_file
+                .map(|env_file| project_file(&project_dir, &env_file))
+                .transpose()?;
+            settings.paths.get_or_insert_with(Vec::new).push(path);
+        }
+        for env in project.envs.into_iter().flatten() {
+            // A project may only allow its own files, not any on the machine
+            let env = match env {
+                AllowedEnv::Path(path) => AllowedEnv::Path(project_file(&project_dir, &path)?),
+                AllowedEnv::Entry {
+                    path,
+                    note,
+                    allowed_at,
+                } => AllowedEnv::Entry {
+                    path: project_file(&project_dir, &path)?,
+                    note,
+                    allowed_at,
+                },
+                AllowedEnv::Glob { glob, .. } => {
+                    bail!("{} cannot allow the glob {glob}", project_config.display())
+                }
+            };
+            let envs = settings.envs.get_or_insert_with(Vec::new);
+            if !envs.iter().any(|allowed| allowed.path() == env.path()) {
+                envs.push(env);
+            }
+        }
+        settings.project_config = Some(project_config);
+        Ok(settings)
+    }
+
+    fn parse(config_path: PathBuf) -> Result<EnvySettings> {
+        let config_dir = config_path
+            .parent()
+            .map(Path::to_path_buf)
+            .unwrap_or_default();
+        let config = Self::read(config_path)?;
+        // Compile all patterns before deserializing the config,
+        // so that an invalid regex can be reported along with its position
+        if let Some(problem) = Self::invalid_patterns(&config).into_iter().next() {
+            bail!(problem);
+        }
+        let mut settings = config
+            .try_deserialize::<EnvySettings>()
+            .context("Cannot deserialize config")?;
+        if let Some(problem) = invalid_default_shell(&settings) {
+            bail!(problem);
+        }
+        settings.config_dir = config_dir;
+        Ok(settings)
+    }
+
+    // Check the config for problems, collecting all of them
+    // instead of stopping at the first one
+    pub fn validate(config_path: PathBuf) -> Result<Vec<String>> {
+        let config = Self::read(config_path)?;
+        let mut problems = Vec::new();
+        match config.get::<Vec<AllowedEnv>>("envs") {
+            Ok(envs) => {
+                for (i, env) in envs.iter().enumerate() {
+                    if let AllowedEnv::Glob { glob, .. } = env {
+                        if !expand_home(glob).is_absolute() {
+                            problems.push(format!("envs[{i}] is not an absolute glob: {glob}"));
+                        }
+                        continue;
+                    }
+                    let env = env.path();
+                    if !env.is_absolute() {
+                        problems.push(format!(
+                            "envs[{i}] is not an absolute path: {}",
+                            env.display()
+                        ));
+                    } else if !env.is_file() {
+                        problems.push(format!("envs[{i}] does not exist: {}", env.display()));
+                    }
+                }
+            }
+            Err(config::ConfigError::NotFound(_)) => {}
+            Err(e) => problems.push(format!("invalid envs: {e}")),
+        }
+        problems.extend(Self::invalid_patterns(&config));
+        // Catch everything else, like unknown types or missing fields
+        if problems.is_empty() {
+            match config.try_deserialize::<EnvySettings>() {
+                Ok(settings) => {
+                    problems.extend(invalid_default_shell(&settings));
+                    if let Err(e) = settings.profile(None) {
+                        problems.push(format!("invalid default_profile: {e}"));
+                    }
+                }
+                Err(e) => problems.push(format!("Cannot deserialize config: {e}")),
+            }
+        }
+        Ok(problems)
+    }
+
+    // Copy a config from a legacy location to the current one, so that
+    // upgrading envy doesn't silently lose the allowed env files.
+    // The legacy config is kept in case an older version is still in use.
+    pub fn migrate_legacy_config(config_path: &Path) -> Result<()> {
+        if config_path.exists() {
+            return Ok(());
+        }
+        let Some(base_dirs) = BaseDirs::new() else {
+            return Ok(());
+        };
+        let legacy_path = LEGACY_CONFIG_PATHS
+            .iter()
+            .map(|path| base_dirs.home_dir().join(path))
+            .find(|path| path.is_file() && path != config_path);
+        let Some(legacy_path) = legacy_path else {
+            return Ok(());
+        };
+        if let Some(parent) = config_path.parent() {
+            fs::create_dir_all(parent).context("Cannot create config directory")?;
+        }
+        fs::copy(&legacy_path, config_path).context("Cannot migrate legacy config")?;
+        eprintln!(
+            "envy: Copied config from {} to {}",
+            legacy_path.display(),
+            config_path.display()
+        );
+        Ok(())
+    }
+
+    fn read(config_path: PathBuf) -> Result<config::Config> {
         config::Config::builder()
             .add_source(config::File::from(config_path))
             .build()
-            .context("Cannot not read config")?
-            .try_deserialize::<EnvySettings>()
-            .context("Cannot deserialize config")
+            .context("Cannot not read config")
+    }
+
+    fn invalid_patterns(config: &config::Config) -> Vec<String> {
+        let paths: Vec<RawPathConfig> = config.get("paths").unwrap_or_default();
+        paths
+            .iter()
+            .enumerate()
+            .filter_map(|(i, path)| {
+                let e = Regex::new(&path.pattern).err()?;
+                Some(format!("invalid regex in paths[{i}]: {e}"))
+            })
+            .collect()
     }
 
     pub fn save(config_path: PathBuf, settings: EnvySettings) -> Result<()> {
         let toml = toml::to_string_pretty(&settings).context("Cannot serialize config")?;
-        fs::write(config_path, toml).context("Cannot write config")
+        let mut new: DocumentMut = toml.parse().context("Cannot serialize config")?;
+        // Keep allowed files with notes in the same list as the plain ones
+        // instead of turning them into `[[envs]]` tables
+        if let Some(envs) = new.get_mut("envs") {
+            if envs.is_array_of_tables() {
+                let array = std::mem::take(envs).into_value().ok();
+                *envs = array.map(Item::Value).unwrap_or_default();
+            }
+        }
+        // Only touch the parts of an existing config that actually changed,
+        // so that comments and formatting of the rest are kept
+        let doc = match fs::read_to_string(&config_path).map(|old| old.parse::<DocumentMut>()) {
+            Ok(Ok(mut doc)) => {
+                keep_env_tables(&doc, &mut new);
+                update_document(&mut doc, &new);
+                doc
+            }
+            _ => new,
+        };
+        fs::write(config_path, doc.to_string()).context("Cannot write config")
+    }
+}
+
+fn invalid_default_shell(settings: &EnvySettings) -> Option<String> {
+    let shell = settings.default_shell.as_deref()?;
+    if SUPPORTED_SHELLS.contains(&shell) {
+        return None;
+    }
+    Some(format!(
+        "unsupported default_shell: {shell} (supported: {})",
+        SUPPORTED_SHELLS.join(", ")
+    ))
+}
+
+// Find the `.envy.toml` in the directory or the closest parent directory
+fn find_project_config(dir: &Path) -> Option<PathBuf> {
+    dir.ancestors()
+        .map(|dir| dir.join(PROJECT_CONFIG_NAME))
+        .find(|config| config.is_file())
+        .and_then(|config| config.canonicalize().ok())
+}
+
+// Resolve a file of a project config against its directory, which it must
+// not leave, neither with `..` nor through a symlink
+fn project_file(project_dir: &Path, path: &Path) -> Result<PathBuf> {
+    let path = project_dir.join(path);
+    // Files that don't exist yet can't be canonicalized, so only drop `..`
+    let resolved = path.canonicalize().unwrap_or_else(|_| {
+        path.components()
+            .fold(PathBuf::new(), |mut resolved, component| {
+                match component {
+                    Component::CurDir => {}
+                    Component::ParentDir => {
+                        resolved.pop();
+                    }
+                    component => resolved.push(component),
+                }
+                resolved
+            })
+    });
+    if !resolved.starts_with(project_dir) {
+        bail!(
+            "{} is outside of the project directory {}",
+            path.display(),
+            project_dir.display()
+        );
+    }
+    Ok(resolved)
+}
+
+// Keep the `env` tables of patterns in `doc` that still contain the same
+// variables, instead of turning them into lists in `new`
+fn keep_env_tables(doc: &DocumentMut, new: &mut DocumentMut) {
+    let old_paths = doc.get("paths").and_then(Item::as_array_of_tables);
+    let new_paths = new.get_mut("paths").and_then(Item::as_array_of_tables_mut);
+    let (Some(old_paths), Some(new_paths)) = (old_paths, new_paths) else {
+        return;
+    };
+    for (old, new) in old_paths.iter().zip(new_paths.iter_mut()) {
+        if old.get("pattern").and_then(Item::as_str) != new.get("pattern").and_then(Item::as_str) {
+            continue;
+        }
+        let Some(table) = old.get("env").and_then(Item::as_table_like) else {
+            continue;
+        };
+        let old_env: Vec<String> = table
+            .iter()
+            .map(|(key, item)| {
+                let value = match item.as_str() {
+                    Some(value) => value.to_string(),
+                    None => item.to_string().trim().to_string(),
+                };
+                format!("{key}={value}")
+            })
+            .collect();
+        let new_env = new.get("env").and_then(Item::as_array).map(|env| {
+            env.iter()
+                .filter_map(Value::as_str)
+                .map(str::to_string)
+                .collect::<Vec<_>>()
+        });
+        if new_env.as_ref() == Some(&old_env) {
+            if let Some(env) = old.get("env") {
+                new.insert("env", env.clone());
+            }
+        }
+    }
+}
+
+// Update all items in `doc` that differ from the ones in `new`
+fn update_document(doc: &mut DocumentMut, new: &DocumentMut) {
+    update_table(doc.as_table_mut(), new.as_table());
+}
+
+// Update the items of a table in place, so that the comments of the ones
+// that didn't change are kept
+fn update_table(old: &mut Table, new: &Table) {
+    let removed: Vec<String> = old
+        .iter()
+        .map(|(key, _)| key.to_string())
+        .filter(|key| !new.contains_key(key))
+        .collect();
+    for key in removed {
+        old.remove(&key);
+    }
+    for (key, item) in new.iter() {
+        match (old.get_mut(key), item) {
+            (Some(Item::Value(Value::Array(old))), Item::Value(Value::Array(array))) => {
+                update_array(old, array)
+            }
+            (Some(Item::ArrayOfTables(old)), Item::ArrayOfTables(tables)) => {
+                update_array_of_tables(old, tables)
+            }
+            (Some(Item::Table(old)), Item::Table(table)) => update_table(old, table),
+            (Some(old), _) if same_item(old, item) => {}
+            _ => old[key] = item.clone(),
+        }
+    }
+}
+
+// Update tables like `[[paths]]` one by one, so that the comments above
+// and inside the ones that are kept stay where they are.
+// Tables are matched by their `pattern`.
+fn update_array_of_tables(old: &mut ArrayOfTables, new: &ArrayOfTables) {
+    let pattern = |table: &Table| {
+        table
+            .get("pattern")
+            .and_then(Item::as_str)
+            .map(str::to_string)
+    };
+    let mut old_tables: Vec<Option<Table>> = old.iter().cloned().map(Some).collect();
+    let mut tables = ArrayOfTables::new();
+    for table in new.iter() {
+        let existing = old_tables
+            .iter_mut()
+            .find(|old| {
+                old.as_ref()
+                    .is_some_and(|old| pattern(old) == pattern(table))
+            })
+            .and_then(Option::take);
+        match existing {
+            Some(mut existing) => {
+                update_table(&mut existing, table);
+                tables.push(existing);
+            }
+            None => tables.push(table.clone()),
+        }
+    }
+    *old = tables;
+}
+
+// Update array elements in place, so that entries like allowed env files
+// keep their order and comments. An element that changed, like an allowed
+// file with a new `allowed_at`, keeps the comments of the old one.
+fn update_array(old: &mut Array, new: &Array) {
+    let same_value =
+        |a: &Value, b: &Value| same_item(&Item::Value(a.clone()), &Item::Value(b.clone()));
+    let same_entry =
+        |a: &Value, b: &Value| matches!((entry_key(a), entry_key(b)), (Some(a), Some(b)) if a == b);
+    // Indent new elements like the existing ones, even if all of them get replaced
+    let indent = old.iter().last().map(|last| indentation(last.decor()));
+    let mut old_values: Vec<Option<Value>> = old.iter().cloned().map(Some).collect();
+    let mut values = Vec::new();
+    let mut appended = Vec::new();
+    for value in new {
+        let position = old_values
+            .iter()
+            .position(|old| old.as_ref().is_some_and(|old| same_value(old, value)))
+            .or_else(|| {
+                old_values
+                    .iter()
+                    .position(|old| old.as_ref().is_some_and(|old| same_entry(old, value)))
+            });
+        match position.and_then(|i| old_values[i].take()) {
+            Some(existing) if same_value(&existing, value) => values.push(existing),
+            Some(existing) => {
+                let mut value = value.clone();
+                *value.decor_mut() = existing.decor().clone();
+                values.push(value);
+            }
+            None => {
+                let mut value = value.clone();
+                if let Some(indent) = &indent {
+                    *value.decor_mut() = indent.clone();
+                }
+                appended.push(values.len());
+                values.push(value);
+            }
+        }
+    }
+    // A comment after the last element, like `"/p/.env", # p`, belongs to
+    // that element, so it must not end up behind the ones added after it
+    let trailing = old.trailing().as_str().unwrap_or_default().to_string();
+    if let (Some(&first), Some(newline)) = (appended.first(), trailing.rfind('\n')) {
+        let comment = trailing[..newline].trim_end();
+        if first > 0 && appended.last() == Some(&(values.len() - 1)) && !comment.is_empty() {
+            let prefix = values[first].decor().prefix().and_then(RawString::as_str);
+            let prefix = format!("{comment}{}", prefix.unwrap_or_default());
+            values[first].decor_mut().set_prefix(prefix);
+            old.set_trailing(&trailing[newline..]);
+        }
+    }
+    old.clear();
+    for value in values {
+        old.push_formatted(value);
+    }
+}
+
+// Get what identifies an element of an array, e.g. the path of an allowed
+// env file, whether it is a plain string or a table with a note
+fn entry_key(value: &Value) -> Option<&str> {
+    match value {
+        Value::String(string) => Some(string.value()),
+        Value::InlineTable(table) => ["path", "glob"]
+            .iter()
+            .find_map(|key| table.get(key).and_then(Value::as_str)),
+        _ => None,
+    }
+}
+
+// Keep only the whitespace around an array element, so that its comments
+// don't get copied to new elements
+fn indentation(decor: &Decor) -> Decor {
+    let whitespace = |raw: Option<&RawString>| {
+        let raw = raw.and_then(RawString::as_str).unwrap_or_default();
+        // Comments end with a newline, the indentation comes after the last one
+        let indent = raw.rfind('\n').map_or(raw, |newline| &raw[newline..]);
+        if indent.trim().is_empty() {
+            indent.to_string()
+        } else {
+            String::new()
+        }
+    };
+    Decor::new(whitespace(decor.prefix()), whitespace(decor.suffix()))
+}
+
+// Compare two TOML items by value, ignoring formatting and comments
+fn same_item(a: &Item, b: &Item) -> bool {
+    let parse = |item: &Item| {
+        let mut item = item.clone();
+        if let Item::Value(value) = &mut item {
+            value.decor_mut().clear();
+        }
+        let mut doc = DocumentMut::new();
+        doc["item"] = item;
+        toml::from_str::<toml::Table>(&doc.to_string()).ok()
+    };
+    parse(a) == parse(b)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Deserialize a config like `Settings::parse` does
+    fn parse(config: &str) -> EnvySettings {
+        config::Config::builder()
+            .add_source(config::File::from_str(config, config::FileFormat::Toml))
+            .build()
+            .unwrap()
+            .try_deserialize()
+            .unwrap()
+    }
+
+    #[test]
+    fn env_as_list_or_table() {
+        let list = parse("[[paths]]\npattern = \".*\"\nenv = [\"FOO=bar\", \"PORT=8080\"]\n");
+        let table = parse("[[paths]]\npattern = \".*\"\nenv = { FOO = \"bar\", PORT = 8080 }\n");
+        assert_eq!(list.paths.unwrap()[0].env, ["FOO=bar", "PORT=8080"]);
+        assert_eq!(table.paths.unwrap()[0].env, ["FOO=bar", "PORT=8080"]);
+    }
+
+    #[test]
+    fn first_matching_pattern_wins() {
+        let settings = parse(
+            "[[paths]]\npattern = \".*work.*\"\nenv = [\"WORK=1\", \"# comment\"]\n\n\
+             [[paths]]\npattern = \".*\"\nenv = [\"ANY=1\"]\n",
+        );
+        let work = settings
+            .matching_pattern_config(Path::new("/home/user/work/project"))
+            .unwrap();
+        assert_eq!(work.pattern.as_str(), ".*work.*");
+        assert_eq!(settings.pattern_env_vars(work).unwrap(), ["WORK=1"]);
+        let other = settings.matching_pattern_config(Path::new("/tmp")).unwrap();
+        assert_eq!(other.pattern.as_str(), ".*");
     }
 }
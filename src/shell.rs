@@ -0,0 +1,130 @@
+use anyhow::{anyhow, Result};
+use std::{env, ffi::OsStr, path::Path, str::FromStr};
+
+/// A shell that envy can export variables for and install a hook into
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Shell {
+    Bash,
+    Zsh,
+    Fish,
+    Tcsh,
+    Csh,
+    Xonsh,
+}
+
+/// Names of all supported shells, as accepted on the command line
+pub const SUPPORTED_SHELLS: [&str; Shell::ALL.len()] = {
+    let mut names = [""; Shell::ALL.len()];
+    let mut i = 0;
+    while i < names.len() {
+        names[i] = Shell::ALL[i].name();
+        i += 1;
+    }
+    names
+};
+
+impl Shell {
+    pub const ALL: [Shell; 6] = [
+        Shell::Bash,
+        Shell::Zsh,
+        Shell::Fish,
+        Shell::Tcsh,
+        Shell::Csh,
+        Shell::Xonsh,
+    ];
+
+    pub const fn name(self) -> &'static str {
+        match self {
+            Shell::Bash => "bash",
+            Shell::Zsh => "zsh",
+            Shell::Fish => "fish",
+            Shell::Tcsh => "tcsh",
+            Shell::Csh => "csh",
+            Shell::Xonsh => "xonsh",
+        }
+    }
+
+    /// Detect the shell from `$SHELL` or the parent process, falling back to bash
+    pub fn detect() -> Self {
+        Self::detect_from(env::var_os("SHELL").as_deref())
+            .or_else(parent_shell)
+            .unwrap_or(Shell::Bash)
+    }
+
+    /// Get the shell that the value of `$SHELL` points to, if envy supports it
+    pub fn detect_from(shell_var: Option<&OsStr>) -> Option<Self> {
+        Self::from_path(Path::new(shell_var?))
+    }
+
+    /// Get the shell at the given path, if envy supports it
+    fn from_path(path: &Path) -> Option<Self> {
+        let name = path.file_name()?.to_string_lossy();
+        // Login shells are started with a leading dash, e.g. `-zsh`
+        name.trim_start_matches('-').parse().ok()
+    }
+}
+
+impl FromStr for Shell {
+    type Err = anyhow::Error;
+
+    fn from_str(name: &str) -> Result<Self> {
+        Shell::ALL
+            .into_iter()
+            .find(|shell| shell.name() == name)
+            .ok_or_else(|| unsupported_shell(name, &SUPPORTED_SHELLS))
+    }
+}
+
+/// Error for a shell that envy can't handle, listing the ones it can
+pub fn unsupported_shell(shell: &str, supported: &[&str]) -> anyhow::Error {
+    anyhow!(
+        "unsupported shell '{shell}'; supported: {}",
+        supported.join(", ")
+    )
+}
+
+/// Get the shell of the parent process if envy supports it (Linux only)
+fn parent_shell() -> Option<Shell> {
+    #[cfg(target_os = "linux")]
+    {
+        let parent = std::os::unix::process::parent_id();
+        let name = std::fs::read_to_string(format!("/proc/{parent}/comm")).ok()?;
+        Shell::from_path(Path::new(name.trim()))
+    }
+    #[cfg(not(target_os = "linux"))]
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn detect(shell_var: &str) -> Option<Shell> {
+        Shell::detect_from(Some(OsStr::new(shell_var)))
+    }
+
+    #[test]
+    fn detect_from_shell_var() {
+        assert_eq!(detect("/usr/bin/fish"), Some(Shell::Fish));
+        assert_eq!(detect("/bin/zsh"), Some(Shell::Zsh));
+        assert_eq!(detect("-bash"), Some(Shell::Bash));
+    }
+
+    #[test]
+    fn detect_unknown_shell() {
+        assert_eq!(detect("/opt/unknown/shell"), None);
+        assert_eq!(Shell::detect_from(None), None);
+    }
+
+    #[test]
+    fn supported_shells_match_names() {
+        for (shell, name) in Shell::ALL.into_iter().zip(SUPPORTED_SHELLS) {
+            assert_eq!(name.parse::<Shell>().unwrap(), shell);
+        }
+        let error = "nushell".parse::<Shell>().unwrap_err().to_string();
+        assert_eq!(
+            error,
+            "unsupported shell 'nushell'; supported: bash, zsh, fish, tcsh, csh, xonsh"
+        );
+    }
+}
@@ -0,0 +1,141 @@
+//! Run `envy allow` and `envy deny` and check the allowed env files.
+
+mod common;
+
+use common::Sandbox;
+
+#[test]
+fn allow_and_deny_directory() {
+    let sandbox = Sandbox::new("allow-dir");
+    sandbox.write("project/.env", "FOO=bar\n");
+
+    common::stdout(sandbox.envy_in("project", &["allow", "--yes", "."]));
+    let envs = sandbox.envy(&["config", "get", "envs"]);
+    let env_file = sandbox.path("project/.env");
+    assert!(envs.contains(&env_file.display().to_string()), "{envs}");
+
+    sandbox.envy(&["deny", "project"]);
+    let envs = sandbox.envy(&["config", "get", "envs"]);
+    assert!(!envs.contains(".env"), "{envs}");
+}
+
+#[test]
+fn deny_deleted_file() {
+    let sandbox = Sandbox::new("deny-deleted");
+    sandbox.write("project/.env", "FOO=bar\n");
+    sandbox.envy(&["allow", "--yes", "project/.env"]);
+    std::fs::remove_file(sandbox.path("project/.env")).unwrap();
+
+    sandbox.envy(&["deny", "project/.env"]);
+    let envs = sandbox.envy(&["config", "get", "envs"]);
+    assert!(!envs.contains(".env"), "{envs}");
+}
+
+#[test]
+fn deny_file_that_is_not_allowed() {
+    let sandbox = Sandbox::new("deny-unknown");
+    sandbox.write("project/.env", "FOO=bar\n");
+    let output = sandbox.envy_in("", &["deny", "project/.env"]);
+    assert!(!output.status.success());
+    assert!(String::from_utf8_lossy(&output.stderr).contains("is not allowed"));
+}
+
+#[test]
+fn allow_does_not_copy_comments() {
+    let sandbox = Sandbox::new("allow-comments");
+    sandbox.write("first/.env", "FOO=bar\n");
+    sandbox.write("second/.env", "FOO=baz\n");
+    sandbox.write(
+        "config/envy/Config.toml",
+        "envs = [\n    # Work project\n    \"{root}/first/.env\",\n]\n",
+    );
+    sandbox.envy(&["allow", "--yes", "second/.env"]);
+    let config = std::fs::read_to_string(sandbox.config()).unwrap();
+    assert_eq!(config.matches("# Work project").count(), 1, "{config}");
+    let second = format!(
+        "\n    {{ path = \"{}\"",
+        sandbox.path("second/.env").display()
+    );
+    assert!(config.contains(&second), "{config}");
+}
+
+/// Export in a project whose env file is allowed with the given entry,
+/// with trust expiring after 30 days
+fn export_with_ttl(name: &str, entry: &str) -> std::process::Output {
+    let sandbox = Sandbox::new(name);
+    sandbox.write("project/.env", "FOO=bar\n");
+    sandbox.write(
+        "config/envy/Config.toml",
+        &format!("trust_ttl_days = 30\nenvs = [{entry}]\n"),
+    );
+    sandbox.envy_in("project", &["export", "bash"])
+}
+
+#[test]
+fn trust_expires_after_ttl() {
+    let entry = "{ path = \"{root}/project/.env\", allowed_at = 1000 }";
+    let output = export_with_ttl("ttl-expired", entry);
+    assert!(String::from_utf8_lossy(&output.stderr).contains("expired after 30 days"));
+    assert_eq!(common::stdout(output), "");
+}
+
+#[test]
+fn trust_without_timestamp_expires() {
+    let output = export_with_ttl("ttl-legacy", "\"{root}/project/.env\"");
+    assert!(String::from_utf8_lossy(&output.stderr).contains("envy allow"));
+    assert_eq!(common::stdout(output), "");
+}
+
+#[test]
+fn recent_trust_does_not_expire() {
+    let now = std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .unwrap()
+        .as_secs();
+    let entry = format!("{{ path = \"{{root}}/project/.env\", allowed_at = {now} }}");
+    let output = export_with_ttl("ttl-recent", &entry);
+    assert_eq!(common::stdout(output), "export FOO=bar\n");
+}
+
+#[test]
+fn allow_keeps_comments_of_existing_entries() {
+    let sandbox = Sandbox::new("allow-keep-comments");
+    sandbox.write("first/.env", "FOO=bar\n");
+    sandbox.write("second/.env", "FOO=baz\n");
+    sandbox.write(
+        "config/envy/Config.toml",
+        "envs = [\n    # first\n    { path = \"{root}/first/.env\", allowed_at = 1 }, # trailing\n]\n",
+    );
+    sandbox.envy(&["allow", "--yes", "second/.env"]);
+    // Allowing it again changes `allowed_at`
+    sandbox.envy(&["allow", "--yes", "first/.env"]);
+    let config = std::fs::read_to_string(sandbox.config()).unwrap();
+    let lines: Vec<&str> = config.lines().collect();
+    assert_eq!(lines[1], "    # first", "{config}");
+    assert!(lines[2].contains("first/.env"), "{config}");
+    assert!(!lines[2].contains("allowed_at = 1 "), "{config}");
+    assert!(lines[2].ends_with("}, # trailing"), "{config}");
+    assert!(lines[3].contains("second/.env"), "{config}");
+    assert!(!lines[3].contains('#'), "{config}");
+}
+
+#[test]
+fn glob_loads_only_the_env_file_of_the_project() {
+    let sandbox = Sandbox::new("allow-glob");
+    sandbox.write("work/a/.env", "PROJECT=a\n");
+    sandbox.write("work/b/.env", "PROJECT=b\n");
+    sandbox.write("other/.env", "PROJECT=other\n");
+    sandbox.envy(&["allow", "--yes", "--glob", "~/work/*/.env"]);
+    std::fs::create_dir_all(sandbox.path("work/a/src")).unwrap();
+
+    for (dir, expected) in [("work/a", "a"), ("work/b", "b"), ("work/a/src", "a")] {
+        let output = sandbox.envy_in(dir, &["export", "bash"]);
+        assert_eq!(
+            common::stdout(output),
+            format!("export PROJECT={expected}\n"),
+            "{dir}"
+        );
+    }
+    let output = sandbox.envy_in("other", &["export", "bash"]);
+    assert_eq!(common::stdout(output), "");
+}
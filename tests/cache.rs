@@ -0,0 +1,59 @@
+//! Check that `envy export` doesn't reuse its cached output after an env file
+//! it would load has been created.
+
+mod common;
+
+use common::Sandbox;
+
+/// Export twice in `dir`, creating `env_file` in between
+fn export_before_and_after(sandbox: &Sandbox, dir: &str, env_file: &str) -> (String, String) {
+    let before = common::stdout(sandbox.envy_in(dir, &["export", "bash"]));
+    sandbox.write(env_file, "FOO=bar\n");
+    let after = common::stdout(sandbox.envy_in(dir, &["export", "bash"]));
+    (before, after)
+}
+
+#[test]
+fn source_up_notices_new_env_file() {
+    let sandbox = Sandbox::new("cache-source-up");
+    sandbox.write("config/envy/Config.toml", "source_up = true\n");
+    sandbox.write("repo/.git/HEAD", "");
+    sandbox.write("repo/sub/.keep", "");
+    let (before, after) = export_before_and_after(&sandbox, "repo/sub", "repo/.env");
+    assert_eq!(before, "");
+    assert_eq!(after, "export FOO=bar\n");
+}
+
+#[test]
+fn glob_notices_new_env_file() {
+    let sandbox = Sandbox::new("cache-glob");
+    sandbox.write(
+        "config/envy/Config.toml",
+        "envs = [{ glob = \"{root}/work/*/.env\" }]\n",
+    );
+    sandbox.write("work/project/.keep", "");
+    let (before, after) = export_before_and_after(&sandbox, "work/project", "work/project/.env");
+    assert_eq!(before, "");
+    assert_eq!(after, "export FOO=bar\n");
+}
+
+#[cfg(feature = "encryption")]
+#[test]
+fn encrypted_env_file_is_not_cached() {
+    use age::secrecy::ExposeSecret;
+
+    let sandbox = Sandbox::new("cache-encrypted");
+    let identity = age::x25519::Identity::generate();
+    let encrypted = age::encrypt(&identity.to_public(), b"SECRET=hunter2\n").unwrap();
+    std::fs::write(sandbox.path("secret.env.age"), encrypted).unwrap();
+
+    let output = sandbox
+        .command(env!("CARGO_BIN_EXE_envy"))
+        .args(["export", "bash", "--file", "secret.env.age"])
+        .env("ENVY_KEY", identity.to_string().expose_secret())
+        .output()
+        .unwrap();
+    assert_eq!(common::stdout(output), "export SECRET=hunter2\n");
+    let cached = std::fs::read_dir(sandbox.path("cache/envy")).map_or(0, |dir| dir.count());
+    assert_eq!(cached, 0);
+}
@@ -0,0 +1,150 @@
+//! Run the commands that neither export for a directory nor allow anything,
+//! like `envy completions`, `envy find`, `envy load`, `envy path` and `envy edit`.
+
+mod common;
+
+use common::Sandbox;
+
+#[test]
+fn bash_completions_contain_subcommands() {
+    let sandbox = Sandbox::new("completions");
+    let completions = sandbox.envy(&["completions", "bash"]);
+    assert!(completions.contains("complete -F _envy"), "{completions}");
+    for subcommand in ["export", "allow", "deny", "hook", "completions"] {
+        assert!(completions.contains(subcommand), "{subcommand} missing");
+    }
+}
+
+#[test]
+fn find_missing_variable_fails() {
+    let sandbox = Sandbox::new("find");
+    let output = sandbox
+        .command(env!("CARGO_BIN_EXE_envy"))
+        .args(["find", "ENVY_TEST_MISSING"])
+        .env_remove("ENVY_TEST_MISSING")
+        .output()
+        .unwrap();
+    assert!(!output.status.success());
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert_eq!(stdout, "Variable ENVY_TEST_MISSING not found\n");
+
+    let output = sandbox
+        .command(env!("CARGO_BIN_EXE_envy"))
+        .args(["find", "ENVY_TEST_FOUND"])
+        .env("ENVY_TEST_FOUND", "1")
+        .output()
+        .unwrap();
+    assert_eq!(common::stdout(output), "1\n");
+}
+
+/// Run `envy edit` with the given editor variables and without `vi` on the `PATH`
+#[cfg(unix)]
+fn edit(sandbox: &Sandbox, editor: Option<&str>, visual: Option<&str>) -> std::process::Output {
+    let mut command = sandbox.command(env!("CARGO_BIN_EXE_envy"));
+    command.arg("edit").env("PATH", sandbox.path("bin"));
+    for (name, value) in [("EDITOR", editor), ("VISUAL", visual)] {
+        match value {
+            Some(value) => command.env(name, value),
+            None => command.env_remove(name),
+        };
+    }
+    command.output().unwrap()
+}
+
+#[cfg(unix)]
+#[test]
+fn edit_falls_back_to_visual() {
+    use std::os::unix::fs::PermissionsExt;
+
+    let sandbox = Sandbox::new("edit");
+    // An editor that records which file it was asked to open
+    sandbox.write("bin/editor", "#!/bin/sh\necho \"$1\" > \"$HOME/edited\"\n");
+    let editor = sandbox.path("bin/editor");
+    std::fs::set_permissions(&editor, std::fs::Permissions::from_mode(0o755)).unwrap();
+    let editor = editor.display().to_string();
+
+    // An empty EDITOR counts as unset
+    for unset_editor in [None, Some("")] {
+        let output = edit(&sandbox, unset_editor, Some(&editor));
+        common::stdout(output);
+        let edited = std::fs::read_to_string(sandbox.path("edited")).unwrap();
+        assert_eq!(edited.trim_end(), sandbox.config().display().to_string());
+    }
+
+    let output = edit(&sandbox, None, None);
+    assert!(!output.status.success());
+    assert!(String::from_utf8_lossy(&output.stderr).contains("Set the EDITOR"));
+}
+
+#[cfg(target_os = "linux")]
+#[test]
+fn path_respects_xdg_config_home() {
+    let sandbox = Sandbox::new("path");
+    let path = sandbox.envy(&["path"]);
+    assert_eq!(path.trim_end(), sandbox.config().display().to_string());
+
+    // A relative `XDG_CONFIG_HOME` is invalid and falls back to `~/.config`
+    let output = sandbox
+        .command(env!("CARGO_BIN_EXE_envy"))
+        .arg("path")
+        .env("XDG_CONFIG_HOME", "relative")
+        .output()
+        .unwrap();
+    let expected = sandbox.path(".config/envy/Config.toml");
+    assert_eq!(
+        common::stdout(output).trim_end(),
+        expected.display().to_string()
+    );
+}
+
+/// Run `envy load` with the content piped to its stdin
+fn load_stdin(sandbox: &Sandbox, args: &[&str], content: &str) -> String {
+    use std::{io::Write, process::Stdio};
+
+    let mut child = sandbox
+        .command(env!("CARGO_BIN_EXE_envy"))
+        .arg("load")
+        .args(args)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .unwrap();
+    child
+        .stdin
+        .take()
+        .unwrap()
+        .write_all(content.as_bytes())
+        .unwrap();
+    common::stdout(child.wait_with_output().unwrap())
+}
+
+#[test]
+fn load_from_stdin() {
+    let sandbox = Sandbox::new("load-stdin");
+    let output = load_stdin(&sandbox, &["-"], "FOO=bar\n# comment\nBAZ=\"a b\"\n");
+    assert_eq!(output, "export FOO=bar\nexport BAZ=\"a b\"\n");
+
+    let output = load_stdin(&sandbox, &["-", "--format", "yaml"], "FOO: bar\n");
+    assert_eq!(output, "export FOO=bar\n");
+}
+
+#[cfg(target_os = "linux")]
+#[test]
+fn config_is_copied_from_legacy_location() {
+    let sandbox = Sandbox::new("legacy");
+    let legacy = "envs = [\"/work/.env\"]\n";
+    sandbox.write(".config/envy/Config.toml", legacy);
+    let config = sandbox.path("xdg/envy/Config.toml");
+    let output = sandbox
+        .command(env!("CARGO_BIN_EXE_envy"))
+        .args(["config", "get", "envs"])
+        .env("XDG_CONFIG_HOME", sandbox.path("xdg"))
+        .output()
+        .unwrap();
+    assert!(String::from_utf8_lossy(&output.stderr).contains("Copied config"));
+    assert!(common::stdout(output).contains("/work/.env"));
+    assert_eq!(std::fs::read_to_string(&config).unwrap(), legacy);
+    // The legacy config is kept for older versions
+    assert!(sandbox.path(".config/envy/Config.toml").is_file());
+}
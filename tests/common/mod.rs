@@ -0,0 +1,91 @@
+//! Fixture shared by the integration tests, which run the envy binary in a
+//! temporary home with its own config and cache.
+// Each test crate only uses some of the helpers
+#![allow(dead_code)]
+
+use std::{
+    env, fs,
+    path::{Path, PathBuf},
+    process::{Command, Output},
+};
+
+/// A temporary home with its own envy config, created with `envy init`
+pub struct Sandbox {
+    pub root: PathBuf,
+}
+
+impl Sandbox {
+    pub fn new(name: &str) -> Self {
+        let root = env::temp_dir().join(format!("envy-test-{name}-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&root);
+        fs::create_dir_all(&root).unwrap();
+        let sandbox = Sandbox {
+            root: root.canonicalize().unwrap(),
+        };
+        sandbox.envy(&["init"]);
+        sandbox
+    }
+
+    /// Get the absolute path of a file in the sandbox
+    pub fn path(&self, file: &str) -> PathBuf {
+        self.root.join(file)
+    }
+
+    /// Get the path of the envy config file
+    pub fn config(&self) -> PathBuf {
+        self.path("config/envy/Config.toml")
+    }
+
+    /// Write a file relative to the sandbox, creating its directories.
+    /// `{root}` in the content is replaced with the path of the sandbox.
+    pub fn write(&self, file: &str, content: &str) -> &Self {
+        let file = self.path(file);
+        fs::create_dir_all(file.parent().unwrap()).unwrap();
+        let content = content.replace("{root}", &self.root.display().to_string());
+        fs::write(file, content).unwrap();
+        self
+    }
+
+    /// Run the program with the sandbox as home and config directory
+    pub fn command(&self, program: impl AsRef<Path>) -> Command {
+        let mut command = Command::new(program.as_ref());
+        command
+            .current_dir(&self.root)
+            .env("HOME", &self.root)
+            .env("XDG_CONFIG_HOME", self.path("config"))
+            .env("XDG_CACHE_HOME", self.path("cache"))
+            .env_remove("ENVY_DISABLE");
+        command
+    }
+
+    /// Run envy with the given arguments in the given directory of the sandbox
+    pub fn envy_in(&self, dir: &str, args: &[&str]) -> Output {
+        self.command(env!("CARGO_BIN_EXE_envy"))
+            .current_dir(self.path(dir))
+            .args(args)
+            .output()
+            .unwrap()
+    }
+
+    /// Run envy with the given arguments and return its output,
+    /// which must succeed
+    pub fn envy(&self, args: &[&str]) -> String {
+        stdout(self.envy_in("", args))
+    }
+}
+
+impl Drop for Sandbox {
+    fn drop(&mut self) {
+        let _ = fs::remove_dir_all(&self.root);
+    }
+}
+
+/// Get the output of a successful command
+pub fn stdout(output: Output) -> String {
+    assert!(
+        output.status.success(),
+        "envy failed: {}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+    String::from_utf8(output.stdout).unwrap()
+}
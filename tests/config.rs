@@ -0,0 +1,57 @@
+//! Manage patterns with `envy config add-path` and `remove-path`.
+
+mod common;
+
+use common::Sandbox;
+
+/// Get the patterns in the config
+fn patterns(sandbox: &Sandbox) -> String {
+    sandbox.envy(&["config", "get", "paths"])
+}
+
+#[test]
+fn add_path() {
+    let sandbox = Sandbox::new("add");
+    sandbox.envy(&["config", "add-path", ".*project.*", "FOO=bar"]);
+    let patterns = patterns(&sandbox);
+    assert!(patterns.contains(".*project.*"), "{patterns}");
+    assert!(patterns.contains("FOO=bar"), "{patterns}");
+}
+
+#[test]
+fn add_duplicate_path_fails() {
+    let sandbox = Sandbox::new("duplicate");
+    sandbox.envy(&["config", "add-path", "foo"]);
+    let output = sandbox.envy_in("", &["config", "add-path", "foo", "FOO=bar"]);
+    assert!(!output.status.success());
+    assert!(String::from_utf8_lossy(&output.stderr).contains("already exists"));
+}
+
+#[test]
+fn remove_path_by_pattern_and_position() {
+    let sandbox = Sandbox::new("remove");
+    for pattern in ["first", "second", "third"] {
+        sandbox.envy(&["config", "add-path", pattern]);
+    }
+    sandbox.envy(&["config", "remove-path", "second"]);
+    sandbox.envy(&["config", "remove-path", "1"]);
+    let patterns = patterns(&sandbox);
+    assert!(!patterns.contains("first"), "{patterns}");
+    assert!(!patterns.contains("second"), "{patterns}");
+    assert!(patterns.contains("third"), "{patterns}");
+    assert!(!sandbox
+        .envy_in("", &["config", "remove-path", "5"])
+        .status
+        .success());
+}
+
+#[test]
+fn add_path_keeps_comments_of_other_patterns() {
+    let sandbox = Sandbox::new("add-comments");
+    let config = "# work pattern\n[[paths]]\n# inside\npattern = \"work\"\nenv = [\n    # a var\n    \"A=1\",\n]\n";
+    sandbox.write("config/envy/Config.toml", config);
+    sandbox.envy(&["config", "add-path", "other", "B=2"]);
+    let saved = std::fs::read_to_string(sandbox.config()).unwrap();
+    assert!(saved.starts_with(config), "{saved}");
+    assert!(saved.contains("pattern = \"other\""), "{saved}");
+}
@@ -0,0 +1,471 @@
+//! Run `envy export` on env files given with `--file` or a project config and
+//! check its output, as well as the shell it detects.
+
+mod common;
+
+use common::{stdout, Sandbox};
+use std::process::{Command, Output};
+
+/// Export the variables of an env file with the given content
+///
+/// `files` are written next to the env file, e.g. templates or a
+/// `config/envy/Config.toml` that replaces the default config.
+fn export(name: &str, content: &str, files: &[(&str, &str)], args: &[&str]) -> Output {
+    let files = [&[(".env", content)], files].concat();
+    run_envy(
+        name,
+        &files,
+        &[&["export", "bash", "--file", ".env"], args].concat(),
+    )
+}
+
+/// Run envy with the given arguments in a sandbox with the files
+fn run_envy(name: &str, files: &[(&str, &str)], args: &[&str]) -> Output {
+    let sandbox = Sandbox::new(name);
+    for (file, content) in files {
+        sandbox.write(file, content);
+    }
+    sandbox.envy_in("", args)
+}
+
+/// Export for bash and return the output, which must succeed
+fn export_bash(name: &str, content: &str) -> String {
+    stdout(export(name, content, &[], &[]))
+}
+
+#[test]
+fn export_prefix_with_tab() {
+    assert_eq!(export_bash("tab", "export\tFOO=bar\n"), "export FOO=bar\n");
+}
+
+#[test]
+fn export_prefix_with_multiple_spaces() {
+    assert_eq!(
+        export_bash("spaces", "export   FOO=bar\nEXPORT BAZ=qux\n"),
+        "export FOO=bar\nexport BAZ=qux\n"
+    );
+}
+
+const TEMPLATE: &str = "host = {{HOST}}\nport = {{ PORT }}\n";
+
+#[test]
+fn template_replaces_placeholders() {
+    let output = export(
+        "template",
+        "HOST=localhost\nPORT=\"8080\"\n",
+        &[("app.conf.tmpl", TEMPLATE)],
+        &["--template", "app.conf.tmpl"],
+    );
+    assert_eq!(stdout(output), "host = localhost\nport = 8080\n");
+}
+
+#[test]
+fn template_with_undefined_variable() {
+    let files = [("app.conf.tmpl", TEMPLATE)];
+    let args = ["--template", "app.conf.tmpl"];
+    let output = export("undefined", "HOST=localhost\n", &files, &args);
+    assert!(!output.status.success());
+    assert!(String::from_utf8_lossy(&output.stderr).contains("PORT"));
+
+    let args = ["--template", "app.conf.tmpl", "--allow-undefined"];
+    let output = export("allow-undefined", "HOST=localhost\n", &files, &args);
+    assert_eq!(stdout(output), "host = localhost\nport = \n");
+}
+
+#[test]
+fn path_add_prepends_without_duplicates() {
+    let config = "[[paths]]\npattern = \".*\"\npath_add = [\"/opt/tool/bin\", \"/usr/bin\"]\n";
+    let output = export(
+        "path-add",
+        "PATH=/bin:/usr/bin\n",
+        &[("config/envy/Config.toml", config)],
+        &[],
+    );
+    // The env file still sets `PATH` before the directories get added
+    let expected = "export PATH=/bin:/usr/bin\nexport PATH=/opt/tool/bin:/usr/bin:/bin\n";
+    assert_eq!(stdout(output), expected);
+}
+
+#[test]
+fn yaml_file_with_colons() {
+    let yaml = "URL: https://example.com:8080/path\nNAME: \"a b\"\n";
+    let output = export("yaml", "", &[("app.yml", yaml)], &["--file", "app.yml"]);
+    assert_eq!(
+        stdout(output),
+        "export URL=https://example.com:8080/path\nexport NAME=\"a b\"\n"
+    );
+}
+
+const PROJECT_CONFIG: &str =
+    "envs = [\"project.env\"]\n\n[[paths]]\npattern = \".*\"\nenv = [\"PROJECT=1\"]\n";
+
+#[test]
+fn project_config_is_merged_once_allowed() {
+    let user_config =
+        "envs = [\"{root}/.envy.toml\"]\n\n[[paths]]\npattern = \"no-match\"\nenv = [\"USER=1\"]\n";
+    let files = [
+        ("config/envy/Config.toml", user_config),
+        (".envy.toml", PROJECT_CONFIG),
+        ("project.env", "FROM_FILE=1\n"),
+    ];
+    let output = run_envy("project", &files, &["export", "bash"]);
+    assert_eq!(stdout(output), "export PROJECT=1\nexport FROM_FILE=1\n");
+}
+
+#[test]
+fn user_patterns_win_over_project_config() {
+    let user_config =
+        "envs = [\"{root}/.envy.toml\"]\n\n[[paths]]\npattern = \".*\"\nenv = [\"USER=1\"]\n";
+    let files = [
+        ("config/envy/Config.toml", user_config),
+        (".envy.toml", PROJECT_CONFIG),
+        ("project.env", "FROM_FILE=1\n"),
+    ];
+    let output = run_envy("project-user", &files, &["export", "bash"]);
+    assert_eq!(stdout(output), "export USER=1\nexport FROM_FILE=1\n");
+}
+
+#[test]
+fn project_config_is_ignored_until_allowed() {
+    let files = [
+        (".envy.toml", PROJECT_CONFIG),
+        ("project.env", "FROM_FILE=1\n"),
+    ];
+    let output = run_envy("project-denied", &files, &["export", "bash"]);
+    assert!(String::from_utf8_lossy(&output.stderr).contains("envy allow"));
+    assert_eq!(stdout(output), "");
+}
+
+/// Detect the shell with the given `$SHELL`
+fn detect_shell(shell: &str) -> String {
+    let output = Command::new(env!("CARGO_BIN_EXE_envy"))
+        .args(["export", "--shell-detect"])
+        .env("SHELL", shell)
+        .output()
+        .unwrap();
+    stdout(output)
+}
+
+#[test]
+fn detect_shell_from_env() {
+    assert_eq!(detect_shell("/usr/bin/fish"), "fish\n");
+    assert_eq!(detect_shell("/bin/zsh"), "zsh\n");
+}
+
+#[test]
+fn detect_unknown_shell_falls_back_to_bash() {
+    // The parent process is the test runner, which isn't a shell either
+    assert_eq!(detect_shell("/opt/unknown/shell"), "bash\n");
+}
+
+#[cfg(unix)]
+#[test]
+fn output_file_is_only_readable_by_the_user() {
+    use std::os::unix::fs::PermissionsExt;
+
+    let sandbox = Sandbox::new("output");
+    sandbox.write(".env", "SECRET=1\n");
+    sandbox.write("out.sh", "");
+    let out = sandbox.path("out.sh");
+    std::fs::set_permissions(&out, std::fs::Permissions::from_mode(0o644)).unwrap();
+    sandbox.envy(&["export", "bash", "--file", ".env", "--output", "out.sh"]);
+    assert_eq!(std::fs::read_to_string(&out).unwrap(), "export SECRET=1\n");
+    let mode = std::fs::metadata(&out).unwrap().permissions().mode();
+    assert_eq!(mode & 0o777, 0o600);
+}
+
+/// Export in `repo` with an allowed project config that has the given `envs`
+fn export_project_envs(name: &str, envs: &str) -> Output {
+    let user_config = "envs = [\"{root}/repo/.envy.toml\"]\n";
+    let files = [
+        ("config/envy/Config.toml", user_config),
+        ("repo/.envy.toml", &*format!("envs = [{envs}]\n")),
+        ("repo/project.env", "INSIDE=1\n"),
+        ("outside.env", "OUTSIDE=1\n"),
+    ];
+    let sandbox = Sandbox::new(name);
+    for (file, content) in files {
+        sandbox.write(file, content);
+    }
+    sandbox.envy_in("repo", &["export", "bash"])
+}
+
+#[test]
+fn project_config_cannot_allow_files_outside() {
+    let output = export_project_envs("project-outside", "\"../outside.env\"");
+    assert!(!output.status.success());
+    assert!(String::from_utf8_lossy(&output.stderr).contains("outside of the project"));
+
+    let output = export_project_envs("project-absolute", "\"{root}/outside.env\"");
+    assert!(!output.status.success());
+}
+
+#[test]
+fn project_config_cannot_allow_globs() {
+    let output = export_project_envs("project-glob", "{ glob = \"{root}/*.env\" }");
+    assert!(!output.status.success());
+    assert!(String::from_utf8_lossy(&output.stderr).contains("glob"));
+}
+
+#[test]
+fn project_config_resolves_relative_paths() {
+    let output = export_project_envs("project-relative", "\"./sub/../project.env\"");
+    assert_eq!(stdout(output), "export INSIDE=1\n");
+}
+
+#[cfg(unix)]
+#[test]
+fn export_in_symlinked_directory() {
+    let sandbox = Sandbox::new("symlink");
+    sandbox.write("project/.env", "FOO=bar\n");
+    sandbox.envy(&["allow", "--yes", "project/.env"]);
+    std::os::unix::fs::symlink(sandbox.path("project"), sandbox.path("link")).unwrap();
+    // The symlink gets resolved before matching the allowed env files
+    let output = sandbox
+        .command(env!("CARGO_BIN_EXE_envy"))
+        .args(["export", "bash", "--dir", "link"])
+        .output()
+        .unwrap();
+    assert_eq!(stdout(output), "export FOO=bar\n");
+}
+
+/// A sandbox with allowed env files in `parent` and `parent/child`
+fn nested_sandbox(name: &str) -> Sandbox {
+    let sandbox = Sandbox::new(name);
+    sandbox.write("parent/.env", "A=parent\nB=parent\n");
+    sandbox.write("parent/child/.env", "B=child\n");
+    // Allow the child first, so that the order of `envs` doesn't decide
+    sandbox.envy(&["allow", "--yes", "parent/child/.env"]);
+    sandbox.envy(&["allow", "--yes", "parent/.env"]);
+    sandbox
+}
+
+#[test]
+fn child_env_file_overrides_parent() {
+    let sandbox = nested_sandbox("nested");
+    let output = sandbox.envy_in("parent/child", &["export", "bash"]);
+    assert_eq!(
+        stdout(output),
+        "export A=parent\nexport B=parent\nexport B=child\n"
+    );
+}
+
+#[test]
+fn envignore_skips_parent_env_files() {
+    let sandbox = nested_sandbox("envignore");
+    sandbox.write("parent/child/.envignore", "");
+    let output = sandbox.envy_in("parent/child", &["export", "bash"]);
+    assert_eq!(stdout(output), "export B=child\n");
+}
+
+/// Export the env file for the shell with the given user config
+/// and return the output, which must succeed
+fn export_with_config(
+    name: &str,
+    shell: &str,
+    content: &str,
+    config: &str,
+    args: &[&str],
+) -> String {
+    let files = [(".env", content), ("config/envy/Config.toml", config)];
+    let args = [&["export", shell, "--file", ".env"], args].concat();
+    stdout(run_envy(name, &files, &args))
+}
+
+#[test]
+fn blocklisted_variable_is_not_exported() {
+    let output = export_with_config(
+        "blocklist",
+        "json",
+        "APP=1\nSECRET_TOKEN=abc\n",
+        "blocklist = [\"SECRET_*\"]\n",
+        &[],
+    );
+    assert_eq!(output, "{\n  \"APP\": \"1\"\n}\n");
+}
+
+#[test]
+fn allowlist_then_blocklist() {
+    let output = export_with_config(
+        "allowlist",
+        "bash",
+        "APP_NAME=envy\nAPP_TOKEN=abc\nOTHER=1\n",
+        "allowlist = [\"APP_*\"]\nblocklist = [\"*_TOKEN\"]\n",
+        &[],
+    );
+    assert_eq!(output, "export APP_NAME=envy\n");
+}
+
+#[test]
+fn prefixed_json_keys() {
+    let files = [(".env", "NAME=envy\nAPP_PORT=8080\n")];
+    let args = ["export", "json", "--file", ".env", "--prefix", "APP_"];
+    let output = run_envy("prefix-json", &files, &args);
+    assert_eq!(
+        stdout(output),
+        "{\n  \"APP_NAME\": \"envy\",\n  \"APP_PORT\": \"8080\"\n}\n"
+    );
+}
+
+#[test]
+fn print_changed_only_skips_unchanged_variables() {
+    let sandbox = Sandbox::new("changed-only");
+    sandbox.write(".env", "SAME=1\nCHANGED=2\nNEW=3\n");
+    let output = sandbox
+        .command(env!("CARGO_BIN_EXE_envy"))
+        .args(["export", "bash", "--file", ".env", "--print-changed-only"])
+        .env("SAME", "1")
+        .env("CHANGED", "1")
+        .output()
+        .unwrap();
+    assert_eq!(stdout(output), "export CHANGED=2\nexport NEW=3\n");
+}
+
+#[test]
+fn sort_variables_by_name() {
+    let output = export("sort", "ZED=1\nALPHA=2\nMIDDLE=3\n", &[], &["--sort"]);
+    assert_eq!(
+        stdout(output),
+        "export ALPHA=2\nexport MIDDLE=3\nexport ZED=1\n"
+    );
+}
+
+#[test]
+fn env_filter_by_prefix() {
+    let content = "APP_NAME=envy\nOTHER=1\nMY_APP_X=2\n";
+    let output = export("env-filter", content, &[], &["--env-filter", "^APP_"]);
+    assert_eq!(stdout(output), "export APP_NAME=envy\n");
+}
+
+#[test]
+fn null_separated_records() {
+    let content = "FOO=bar\nSPACED=\"a b\"\nunset FOO\nBAZ=qux\n";
+    let output = export("null", content, &[], &["--null"]);
+    assert_eq!(stdout(output), "SPACED=a b\0BAZ=qux\0");
+}
+
+const PROFILES: &str = "default_profile = \"dev\"\n\n\
+    [profiles.dev]\nenv = [\"STAGE=dev\"]\n\n\
+    [profiles.prod]\nenv = { STAGE = \"prod\" }\n";
+
+#[test]
+fn profile_overrides_env_file() {
+    let output = export_with_config(
+        "profile",
+        "bash",
+        "STAGE=local\n",
+        PROFILES,
+        &["--profile", "prod"],
+    );
+    assert_eq!(output, "export STAGE=local\nexport STAGE=prod\n");
+}
+
+#[test]
+fn default_profile_without_option() {
+    let output = export_with_config("default-profile", "bash", "STAGE=local\n", PROFILES, &[]);
+    assert_eq!(output, "export STAGE=local\nexport STAGE=dev\n");
+}
+
+#[test]
+fn empty_and_unset_variables() {
+    let content = "EMPTY=\nQUOTED=\"\"\nunset GONE\n";
+    let output = export("empty-bash", content, &[], &[]);
+    assert_eq!(
+        stdout(output),
+        "export EMPTY=\nexport QUOTED=\"\"\nunset GONE\n"
+    );
+    let files = [(".env", content)];
+    let output = run_envy("empty-fish", &files, &["export", "fish", "--file", ".env"]);
+    assert_eq!(
+        stdout(output),
+        "set -gx EMPTY \nset -gx QUOTED \"\"\nset -e GONE\n"
+    );
+}
+
+#[test]
+fn append_to_existing_path() {
+    let sandbox = Sandbox::new("append");
+    sandbox.write(".env", "PATH+=/opt/tool/bin\n");
+    let output = sandbox
+        .command(env!("CARGO_BIN_EXE_envy"))
+        .args(["export", "bash", "--file", ".env"])
+        .env("PATH", "/usr/bin:/bin")
+        .output()
+        .unwrap();
+    assert_eq!(stdout(output), "export PATH=/usr/bin:/bin:/opt/tool/bin\n");
+}
+
+#[test]
+fn which_matches_export() {
+    let sandbox = Sandbox::new("which");
+    sandbox.write("parent/.env", "FOO=1\nBAR=1\nGH_TOKEN=abc\n");
+    sandbox.write("parent/child/.env", "unset FOO\nBAR=2\n");
+    sandbox.write("config/envy/Config.toml", "blocklist = [\"*_TOKEN\"]\n");
+    sandbox.envy(&["allow", "--yes", "parent/.env"]);
+    sandbox.envy(&["allow", "--yes", "parent/child/.env"]);
+
+    let output = sandbox.envy_in("parent/child", &["which", "BAR"]);
+    let child = sandbox.path("parent/child/.env");
+    assert_eq!(stdout(output), format!("{}\n", child.display()));
+    for variable in ["FOO", "GH_TOKEN"] {
+        let output = sandbox.envy_in("parent/child", &["which", variable]);
+        assert!(!output.status.success(), "{variable}");
+        assert_eq!(String::from_utf8_lossy(&output.stdout), "");
+    }
+    let output = sandbox.envy_in("parent/child", &["export", "bash"]);
+    assert_eq!(
+        stdout(output),
+        "export FOO=1\nexport BAR=1\nunset FOO\nexport BAR=2\n"
+    );
+}
+
+#[test]
+fn envy_disable_skips_export() {
+    let sandbox = Sandbox::new("disable");
+    sandbox.write(".env", "FOO=bar\n");
+    let export = |value: &str| {
+        sandbox
+            .command(env!("CARGO_BIN_EXE_envy"))
+            .args(["export", "bash", "--file", ".env"])
+            .env("ENVY_DISABLE", value)
+            .output()
+            .unwrap()
+    };
+    assert_eq!(stdout(export("1")), "");
+    assert_eq!(stdout(export("0")), "export FOO=bar\n");
+}
+
+#[test]
+fn defaults_are_only_used_in_references() {
+    let sandbox = Sandbox::new("defaults");
+    sandbox.write(
+        "config/envy/Config.toml",
+        "defaults_file = \"defaults.env\"\n",
+    );
+    sandbox.write("config/envy/defaults.env", "HOST=db.local\nPORT=5432\n");
+    sandbox.write(
+        ".env",
+        "PORT=6000\nURL=http://${HOST}:${PORT}\n\
+         LITERAL='${HOST}'\nUNKNOWN=${MISSING}/${HOST\n",
+    );
+    let output = sandbox.envy(&["export", "bash", "--file", ".env"]);
+    assert_eq!(
+        output,
+        "export PORT=6000\nexport URL=http://db.local:6000\n\
+         export LITERAL='${HOST}'\nexport UNKNOWN=${MISSING}/${HOST\n"
+    );
+}
+
+#[test]
+fn warn_about_names_that_only_differ_in_case() {
+    let output = export("case", "Foo=1\nOTHER=2\nFOO=3\n", &[], &[]);
+    let stderr = String::from_utf8_lossy(&output.stderr).to_string();
+    assert!(
+        stderr.contains("variable names only differ in case: Foo, FOO"),
+        "{stderr}"
+    );
+    assert_eq!(
+        stdout(output),
+        "export Foo=1\nexport OTHER=2\nexport FOO=3\n"
+    );
+}
@@ -0,0 +1,132 @@
+//! Source the generated hooks in real shells and check that they export the
+//! variables of an allowed env file. Shells that aren't installed are skipped.
+#![cfg(unix)]
+
+mod common;
+
+use common::Sandbox;
+use std::process::Command;
+
+// Values that are easy to break with wrong quoting
+const ENV_FILE: &str = "GREETING='hello world'\nQUOTED=\"it's here\"\nPLAIN=value\n";
+
+/// A sandbox with an allowed project directory
+fn project_sandbox(name: &str) -> Sandbox {
+    let sandbox = Sandbox::new(name);
+    sandbox.write("project/.env", ENV_FILE);
+    sandbox.envy(&["allow", "--yes", "project/.env"]);
+    sandbox
+}
+
+/// Run the script in the shell and return its output
+fn run(sandbox: &Sandbox, shell: &str, script: &str) -> String {
+    let output = sandbox
+        .command(shell)
+        .arg("-c")
+        .arg(script)
+        .output()
+        .unwrap();
+    assert!(
+        output.status.success(),
+        "{shell} failed: {}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+    String::from_utf8(output.stdout).unwrap()
+}
+
+fn has_shell(shell: &str) -> bool {
+    Command::new(shell)
+        .args(["-c", "true"])
+        .output()
+        .is_ok_and(|output| output.status.success())
+}
+
+const EXPECTED: &str = "hello world|it's here|value\n";
+
+#[test]
+fn bash_hook_exports_variables() {
+    if !has_shell("bash") {
+        return;
+    }
+    let sandbox = project_sandbox("bash");
+    let script = format!(
+        r#"eval "$('{}' hook bash)"; cd '{}'; eval "$PROMPT_COMMAND"; bash -c 'printf "%s|%s|%s\n" "$GREETING" "$QUOTED" "$PLAIN"'"#,
+        env!("CARGO_BIN_EXE_envy"),
+        sandbox.path("project").display()
+    );
+    assert_eq!(run(&sandbox, "bash", &script), EXPECTED);
+}
+
+#[test]
+fn zsh_hook_exports_variables() {
+    if !has_shell("zsh") {
+        return;
+    }
+    let sandbox = project_sandbox("zsh");
+    let script = format!(
+        r#"eval "$('{}' hook zsh)"; cd '{}'; for f in $precmd_functions; do $f; done; sh -c 'printf "%s|%s|%s\n" "$GREETING" "$QUOTED" "$PLAIN"'"#,
+        env!("CARGO_BIN_EXE_envy"),
+        sandbox.path("project").display()
+    );
+    assert_eq!(run(&sandbox, "zsh", &script), EXPECTED);
+}
+
+#[test]
+fn fish_hook_exports_variables() {
+    if !has_shell("fish") {
+        return;
+    }
+    let sandbox = project_sandbox("fish");
+    let script = format!(
+        r#"'{}' hook fish | source; cd '{}'; emit fish_prompt; sh -c 'printf "%s|%s|%s\n" "$GREETING" "$QUOTED" "$PLAIN"'"#,
+        env!("CARGO_BIN_EXE_envy"),
+        sandbox.path("project").display()
+    );
+    assert_eq!(run(&sandbox, "fish", &script), EXPECTED);
+}
+
+#[test]
+fn xonsh_hook_uses_prompt_event() {
+    let sandbox = Sandbox::new("xonsh");
+    let hook = sandbox.envy(&["hook", "xonsh"]);
+    assert!(hook.contains("@events.on_pre_prompt"), "{hook}");
+    assert!(hook.contains("export xonsh"), "{hook}");
+}
+
+#[test]
+fn hook_unsupported_shell_lists_supported_ones() {
+    let sandbox = Sandbox::new("unsupported");
+    let output = sandbox.envy_in("", &["hook", "nushell"]);
+    assert!(!output.status.success());
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(
+        stderr.contains("unsupported shell 'nushell'; supported: bash, zsh, fish"),
+        "{stderr}"
+    );
+}
+
+/// Get the envy path that the hook would call when run as `program`
+fn hook_path(sandbox: &Sandbox, program: &str) -> String {
+    let output = sandbox
+        .command(program)
+        .env("PATH", sandbox.path("bin"))
+        .args(["hook", "--print-path"])
+        .output()
+        .unwrap();
+    let output = common::stdout(output);
+    let line = output.lines().next().unwrap();
+    line.strip_prefix("Hook path: ").unwrap().to_string()
+}
+
+#[test]
+fn hook_keeps_the_invoked_path() {
+    let sandbox = Sandbox::new("self-path");
+    let link = sandbox.path("bin/envy");
+    std::fs::create_dir_all(sandbox.path("bin")).unwrap();
+    std::os::unix::fs::symlink(env!("CARGO_BIN_EXE_envy"), &link).unwrap();
+    let link = link.display().to_string();
+    // The symlink stays as it is instead of being resolved to the binary
+    assert_eq!(hook_path(&sandbox, &link), link);
+    // A bare name is looked up on the `PATH`
+    assert_eq!(hook_path(&sandbox, "envy"), link);
+}